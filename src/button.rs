@@ -1,6 +1,7 @@
 use super::{rgb};
 use super::rect::{Rect};
 use wgpu::{Device};
+#[allow(dead_code)]
 pub enum BtnContent {
     Image(String),
     Text(String),
@@ -16,7 +17,7 @@ pub struct ButtonBuilder {
 }
 impl ButtonBuilder {
     pub fn new(screen_size : (u32,u32)) -> Self {
-        ButtonBuilder { screen_size, size: (100,75), pos: (0,0), color: rgb(super::margin_bg_color), content: BtnContent::None}
+        ButtonBuilder { screen_size, size: (100,75), pos: (0,0), color: rgb(super::MARGIN_BG_COLOR), content: BtnContent::None}
     }
     pub fn size(mut self, size : (u32,u32)) -> Self {
         self.size = size;
@@ -30,6 +31,7 @@ impl ButtonBuilder {
         self.color = color;
         self
     }
+    #[allow(dead_code)]
     pub fn content(mut self, content : BtnContent) -> Self {
         self.content = content;
         self
@@ -43,6 +45,7 @@ impl ButtonBuilder {
 
 pub struct Button {
     pub rect : Rect,
+    #[allow(dead_code)]
     content : BtnContent 
 }
 	 
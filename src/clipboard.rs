@@ -0,0 +1,39 @@
+// Where copied text goes. The frontend plugs in the system clipboard, and without a
+//  window the editor keeps copied text to itself.
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum ClipboardKind {
+    Clipboard, // ctrl+c / ctrl+v
+    Primary // whatever is selected, pasted with the middle mouse button (X11 and Wayland)
+}
+
+pub trait Clipboard {
+    fn get(&mut self, kind : ClipboardKind) -> Option<String>;
+    fn set(&mut self, kind : ClipboardKind, text : String);
+
+    // Why copying last went wrong, if it did. Only once, so the same error isn't shown twice.
+    fn take_error(&mut self) -> Option<String> {
+        None
+    }
+}
+
+// A clipboard that only this editor can see.
+#[derive(Default)]
+pub struct MemoryClipboard {
+    clipboard : Option<String>,
+    primary : Option<String>
+}
+impl Clipboard for MemoryClipboard {
+    fn get(&mut self, kind : ClipboardKind) -> Option<String> {
+        match kind {
+            ClipboardKind::Clipboard => self.clipboard.clone(),
+            ClipboardKind::Primary => self.primary.clone()
+        }
+    }
+    fn set(&mut self, kind : ClipboardKind, text : String) {
+        match kind {
+            ClipboardKind::Clipboard => self.clipboard = Some(text),
+            ClipboardKind::Primary => self.primary = Some(text)
+        }
+    }
+}
@@ -1,203 +1,402 @@
-use crate::State;
+use std::ops::{Range, RangeInclusive};
+use super::document::Document;
+use super::layout::Line;
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
 
-use super::rect;
-use super::Line;
-use wgpu::Device;
-use wgpu_glyph::{Text,GlyphBrush,SectionGeometry, GlyphPositioner,SectionGlyph, ab_glyph::{Font, FontArc}};
+#[derive(Clone,Copy)]
+pub enum CursorMovement {
+    Up,Down,Left,Right, // up and down go by rows on screen, so through a wrapped line a row at a time
+    LineUp,LineDown, // up and down by whole lines, however many rows they wrap to
+    WordLeft,WordRight, // ctrl+arrows
+    Home,End, // the ends of the row, then the ends of the line. Home stops at the indentation first.
+    PageUp(usize),PageDown(usize), // by this many rows
+    DocumentStart,DocumentEnd // ctrl+home and ctrl+end
+}
+
+// Ways to take text out besides backspace.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Deletion {
+    Forward, // delete
+    WordLeft, // ctrl+backspace
+    WordRight, // ctrl+delete
+    Line // ctrl+shift+k
+}
+
+// The word around index, by unicode word boundaries. Empty if index isn't touching a word.
+pub fn word_at(text : &str, index : usize) -> Range<usize> {
+    for (start,word) in text.split_word_bound_indices() {
+        let end = start + word.len();
+        if start <= index && index <= end && word.chars().any(|character| character.is_alphanumeric() || character == '_') {
+            return start..end;
+        }
+        if start > index {
+            break;
+        }
+    }
+    index..index
+}
+
+// The start of the word before index, skipping any space in between. 0 if there isn't one.
+pub fn prev_word_boundary(text : &str, index : usize) -> usize {
+    let mut boundary = 0;
+    for (start,word) in text.split_word_bound_indices() {
+        if start >= index {
+            break;
+        }
+        if !word.trim().is_empty() {
+            boundary = start;
+        }
+    }
+    boundary
+}
+
+// The end of the word after index, skipping any space in between. The end of the text if there isn't one.
+pub fn next_word_boundary(text : &str, index : usize) -> usize {
+    text.split_word_bound_indices()
+        .map(|(start,word)| (start + word.len(), word))
+        .find(|(end,word)| *end > index && !word.trim().is_empty())
+        .map(|(end,_)| end)
+        .unwrap_or(text.len())
+}
+
+// A cursor is also a selection, from the anchor to pos. When they are the same, nothing is selected.
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub struct Cursor {
-    pos : (usize,usize),
-    pub rect : rect::Rect,
+    pub pos : (usize,usize), // (byte index into the line, line). Always on a grapheme boundary.
+    pub anchor : (usize,usize), // where the selection started. pos is the end that moves.
+    // the x (in pixels, from the start of the row) that up and down try to get back to, after
+    //  passing through shorter rows. Forgotten when the cursor moves sideways or the text changes.
+    pub goal : Option<f32>
+}
+
+// Cursor columns step over whole grapheme clusters, so 'é' written as e + U+0301, or an
+//  emoji made of several code points, is one step and can't be split.
 
-    font_size : f32,
-    screen_size : (u32,u32)
+// The grapheme boundary before index, or 0.
+pub fn prev_grapheme(text : &str, index : usize) -> usize {
+    let mut cursor = GraphemeCursor::new(index, text.len(), true);
+    cursor.prev_boundary(text, 0).ok().flatten().unwrap_or(0)
 }
 
+// The grapheme boundary after index, or the end of the text.
+pub fn next_grapheme(text : &str, index : usize) -> usize {
+    let mut cursor = GraphemeCursor::new(index, text.len(), true);
+    cursor.next_boundary(text, 0).ok().flatten().unwrap_or(text.len())
+}
+
+// index if it's on a grapheme boundary, otherwise the boundary after it. Edits can join
+//  graphemes together, like typing 'e' before a combining accent, and leave an index inside one.
+pub fn snap_to_grapheme(text : &str, index : usize) -> usize {
+    let mut cursor = GraphemeCursor::new(index, text.len(), true);
+    if cursor.is_boundary(text, 0).unwrap_or(true) { index } else { next_grapheme(text, index) }
+}
+
+// The column index is drawn at, counting graphemes, with a tab reaching to the next tab stop.
+pub fn visual_column(text : &str, index : usize, tab_width : usize) -> usize {
+    text[..index].graphemes(true).fold(0, |column,grapheme| {
+        if grapheme == "\t" { column + tab_width - column % tab_width } else { column + 1 }
+    })
+}
+
+// The byte index at a visual column. Inside a tab is before it, and past the end of the line is the end.
+pub fn visual_column_to_index(text : &str, column : usize, tab_width : usize) -> usize {
+    let mut current = 0;
+    for (index,grapheme) in text.grapheme_indices(true) {
+        current = if grapheme == "\t" { current + tab_width - current % tab_width } else { current + 1 };
+        if current > column {
+            return index;
+        }
+    }
+    text.len()
+}
 
 impl Cursor {
-    pub fn new(state : &super::State, pos : (usize,usize)) -> Self {
-        let color = super::rgb(super::margin_bg_color);
-        let screen_size = (state.size.width,state.size.height); // size of the screen
-        let rect = rect::Rect::new(&state.device,screen_size, (1,1),(0,0), (0,0),color);
-        let mut cursor = Cursor { pos, rect, font_size: state.font_scale, screen_size};
+    pub fn new(pos : (usize,usize)) -> Self {
+        Cursor { pos, anchor : pos, goal : None }
+    }
 
+    // A cursor with something selected, from anchor to pos.
+    pub fn selecting(anchor : (usize,usize), pos : (usize,usize)) -> Self {
+        Cursor { pos, anchor, goal : None }
+    }
 
-        let cursor_pos = cursor.calc_cursor_pos(&state.glyph_brush,  &state.lines[pos.1])
-        .expect("0,0 should be a valid cursor location, all the time.");
+    pub fn has_selection(&self) -> bool {
+        self.pos != self.anchor
+    }
 
-        // update the new cursor
-        // cursor.rect.set_pos(&state.device, x, y) = ( as usize, cursor_pos.1 as usize);
-        cursor.update_cursor(&state.device, &state.glyph_brush,state.scroll as i64 * state.font_scale as i64, &state.lines);
-        cursor
+    // The ends of the selection, first one first.
+    pub fn selection(&self) -> ((usize,usize),(usize,usize)) {
+        if (self.pos.1,self.pos.0) < (self.anchor.1,self.anchor.0) {
+            (self.pos, self.anchor)
+        } else {
+            (self.anchor, self.pos)
+        }
     }
 
-    fn calc_cursor_pos(&self, glyph_brush : &GlyphBrush<()>, text : &Line) -> Option<(i64,i64,u32)> {
-        let font = &glyph_brush.fonts()[0];
+    // The selection as bytes of the document.
+    pub fn selection_range(&self, document : &Document) -> Range<usize> {
+        let (start,end) = self.selection();
+        document.pos_to_byte(start)..document.pos_to_byte(end)
+    }
 
-        // let mut text_acc = 0;
-        let mut x = 0;
+    // Where the cursor is drawn, relative to the top of its line. (x, y, width)
+    pub fn calc_cursor_pos(&self, line : &Line, line_height : f32) -> Option<(i64,i64,u32)> {
         let mut y = 0;
         let mut last_x_px : i64 = 0;
-        for line in &text.glyphs {
+        let mut containing = None;
+        for row in &line.glyphs {
             last_x_px = 0;
-            for SectionGlyph{glyph,..} in line {
-                // Get the width of the glyph
-                let bound = font.glyph_bounds(glyph);
-                let width = bound.width().round() as u32;
-    
+            for glyph in row {
+                let rect = (glyph.x.round() as i64, (y as f32 * line_height) as i64, glyph.width.round() as u32);
                 // This glyph is what the cursor is highlighting. Return its position.
-                if x == self.pos.0 {
-                    return Some( (glyph.position.x.round() as i64, (y as f32 * self.font_size) as i64,width))
+                if glyph.index == self.pos.0 {
+                    return Some(rect)
+                }
+                // in case pos is in the middle of a grapheme, highlight the whole thing.
+                if glyph.index < self.pos.0 {
+                    containing = Some(rect);
                 }
-                
-                last_x_px = (glyph.position.x.round() as u32 + width) as i64;
-                x += 1;
+
+                last_x_px = (glyph.x + glyph.width).round() as i64;
             }
 
-            // text_acc += line.len();
             y += 1;
         }
         y-=1; // to get rid of the last y change, so the cursor can hang off the side of the line.
 
-        if x == self.pos.0 {
+        if self.pos.0 == line.text_len() {
             // Only runs for the cursor position right after the last character.
-            return Some( ( last_x_px, (y as f32 * self.font_size) as i64, 8) );
+            return Some( ( last_x_px, (y as f32 * line_height) as i64, 8) );
         }
-        
-        None
-    }
 
-    pub fn update_screen_size(&mut self,device : &wgpu::Device, screen_size: (u32,u32)) {
-        self.screen_size = screen_size;
-        self.rect.update_rect(device, screen_size);
+        containing
     }
 
-    // scaled_scroll is a scroll of pixels.
-    pub fn update_cursor(&mut self,device : &Device , glyph_brush : &GlyphBrush<()>,scaled_scroll : i64, text : &Vec<Line>) {
-        // get number of lines proceeding.
-        let mut y_acc = 0;
-        for i in 0..self.pos.1 { // doesn't include self.pos.1
-            y_acc += text[i].glyphs.len() as i64;
-        }
-        // update cursor rectangle position.        
-        let (x,y,w) = self.calc_cursor_pos(glyph_brush, &text[self.pos.1])
-            .expect("You are bad at programming.");
-        self.rect.set_rect(device,self.screen_size,x,y + y_acc * (self.font_size as i64) - scaled_scroll, w,self.font_size as u32);
-    }
+    // With select, the anchor stays put and the selection grows or shrinks.
+    pub fn move_cursor(&mut self, document : &Document, direction : CursorMovement, select : bool) {
+        use CursorMovement::*;
+        let lines = document.len_lines();
+
+        // moving up and down remembers where it started from, anything else forgets.
+        let goal = match direction {
+            Up | Down | LineUp | LineDown | PageUp(_) | PageDown(_) => Some(self.goal.unwrap_or_else(|| self.x(document))),
+            _ => None
+        };
+
+        // Left and right out of a selection just go to that side of it.
+        if !select && self.has_selection() {
+            let (start,end) = self.selection();
+            match direction {
+                Left => { *self = Cursor::new(start); return; }
+                Right => { *self = Cursor::new(end); return; }
+                _ => {}
+            }
+        }
 
-    // Does not update the cursor's rectangle.
-    pub fn move_cursor(&mut self,text : &Vec<&String>, direction : super::CursorMovement) {
-        // TODO: Add ghost cursor to try to keep horizontal position. This is opinionated!
-        use super::CursorMovement::*;
         match direction {
             Left => {
                 if self.pos.0 > 0 {
-                    self.pos.0 -= 1;
+                    self.pos.0 = prev_grapheme(&document.line(self.pos.1), self.pos.0);
                 }else if self.pos.1 > 0{
                     // move to line above
                     self.pos.1 -= 1;
-                    self.pos.0 = text[self.pos.1].len();
+                    self.pos.0 = document.line_len(self.pos.1);
                 }
             }
             Right => {
                 // if there is room on this line
-                if self.pos.0 < text[self.pos.1].len() {
-                    self.pos.0 += 1;
+                if self.pos.0 < document.line_len(self.pos.1) {
+                    self.pos.0 = next_grapheme(&document.line(self.pos.1), self.pos.0);
 
                 // If there is another line below to move too.
-                }else if text.len()-1 > self.pos.1{
+                }else if lines-1 > self.pos.1{
                     // move to line below
                     self.pos.0 = 0;
                     self.pos.1 += 1;
                 }
             }
-            Down => {
-                if text.len()-1 > self.pos.1 {
+            Up => self.move_rows(document, -1, goal.unwrap()),
+            Down => self.move_rows(document, 1, goal.unwrap()),
+            LineDown => {
+                if lines-1 > self.pos.1 {
+                    // the first row of the next line, as close to the goal as it gets.
                     self.pos.1 += 1;
-                    // move .0 to the correct place.
-                    if self.pos.0 > text[self.pos.1].len() {
-                        self.pos.0 = text[self.pos.1].len();
-                    }
+                    self.pos.0 = document.lines[self.pos.1].index_at(0, goal.unwrap());
                 }
             }
-            Up => {
+            LineUp => {
                 if self.pos.1 > 0 {
                     self.pos.1 -= 1;
-                    //move .0
-                    if self.pos.0 > text[self.pos.1].len() {
-                        self.pos.0 = text[self.pos.1].len();
-                    }
+                    let line = &document.lines[self.pos.1];
+                    self.pos.0 = line.index_at(line.glyphs.len()-1, goal.unwrap());
+                }
+            }
+            WordLeft => {
+                if self.pos.0 > 0 {
+                    self.pos.0 = prev_word_boundary(&document.line(self.pos.1), self.pos.0);
+                }else if self.pos.1 > 0 {
+                    self.pos.1 -= 1;
+                    self.pos.0 = document.line_len(self.pos.1);
                 }
             }
-            _ => {}
-        }   
+            WordRight => {
+                if self.pos.0 < document.line_len(self.pos.1) {
+                    self.pos.0 = next_word_boundary(&document.line(self.pos.1), self.pos.0);
+                }else if lines-1 > self.pos.1 {
+                    self.pos.0 = 0;
+                    self.pos.1 += 1;
+                }
+            }
+            Home => {
+                let line = &document.lines[self.pos.1];
+                let row_start = line.breaks[line.row_of(self.pos.0)];
+                if self.pos.0 != row_start && row_start != 0 {
+                    self.pos.0 = row_start;
+                } else {
+                    // smart home, back and forth between the indentation and the very start.
+                    let text = document.line(self.pos.1);
+                    let indentation = text.len() - text.trim_start().len();
+                    self.pos.0 = if self.pos.0 == indentation { 0 } else { indentation };
+                }
+            }
+            End => {
+                let line = &document.lines[self.pos.1];
+                let row = line.row_of(self.pos.0);
+                // the end of a wrapped row is the same index as the start of the next one, so stop just before it.
+                let row_end = if row+1 < line.glyphs.len() { prev_grapheme(&document.line(self.pos.1), line.breaks[row+1]) } else { line.text_len() };
+                self.pos.0 = if self.pos.0 < row_end { row_end } else { line.text_len() };
+            }
+            PageUp(rows) => self.move_rows(document, -(rows as isize), goal.unwrap()),
+            PageDown(rows) => self.move_rows(document, rows as isize, goal.unwrap()),
+            DocumentStart => self.pos = (0,0),
+            DocumentEnd => self.pos = (document.line_len(lines-1), lines-1)
+        }
+
+        self.goal = goal;
+        if !select {
+            self.anchor = self.pos;
+        }
     }
 
-    // Given a line (a string and a list of breakers), return the slice of the string that the cursor is in.
-    pub fn get_cursor_inline<'a>(&self, text : &'a(String,Vec<usize>)) -> Option<&'a str> {
-        let mut acc = 0;
-        for i in 0..text.1.len() {
-            if self.pos.0 < text.1[i] {
-                return Some( &text.0[text.1[i-1]..text.1[i]]);
+    // How far along its row the cursor is, in pixels.
+    fn x(&self, document : &Document) -> f32 {
+        let line = &document.lines[self.pos.1];
+        line.x_at(line.row_of(self.pos.0), self.pos.0)
+    }
+
+    // Move up (negative) or down some rows of the wrapped text, to about x on that row.
+    //  Running out of rows goes to the start or end of the document.
+    fn move_rows(&mut self, document : &Document, rows : isize, x : f32) {
+        let (mut line, mut row) = (self.pos.1, document.lines[self.pos.1].row_of(self.pos.0));
+
+        for _ in 0..rows.unsigned_abs() {
+            if rows < 0 {
+                if row > 0 {
+                    row -= 1;
+                } else if line > 0 {
+                    line -= 1;
+                    row = document.lines[line].glyphs.len() - 1;
+                } else {
+                    self.pos = (0,0);
+                    return;
+                }
+            } else if row+1 < document.lines[line].glyphs.len() {
+                row += 1;
+            } else if line+1 < document.len_lines() {
+                line += 1;
+                row = 0;
+            } else {
+                self.pos = (document.line_len(line), line);
+                return;
             }
         }
-        None
+        self.pos = (document.lines[line].index_at(row, x), line);
     }
-    pub fn insert_text(&mut self,glyph_brush : &GlyphBrush<()>, lines : &mut Vec<Line>, character : char) {
-        let Line { text, breaks, glyphs } = &mut lines[self.pos.1];
+
+    // The lines the selection covers. A selection that ends at the start of a line doesn't count that line.
+    pub fn selected_lines(&self) -> RangeInclusive<usize> {
+        let (start,end) = self.selection();
+        if end.0 == 0 && end.1 > start.1 {
+            start.1..=end.1-1
+        } else {
+            start.1..=end.1
+        }
+    }
+
+    // What typing this character would do to the document, as a range of bytes to replace
+    //  and the text to replace it with. The editor applies it and moves the cursor.
+    pub fn insert_text(&self, document : &Document, character : char) -> Option<(Range<usize>,String)> {
+        let index = document.pos_to_byte(self.pos);
+
+        // Typing over a selection replaces it, and backspace just deletes it.
+        if self.has_selection() {
+            let range = self.selection_range(document);
+            return match character {
+                '\r' => Some( (range, String::from("\n")) ),
+                '\t' => Some( (range, document.indent.text(0)) ),
+                '\u{8}' => Some( (range, String::new()) ),
+                character if !character.is_control() => Some( (range, character.to_string()) ),
+                _ => None
+            };
+        }
+
         match character {
             '\r' => {
-                if self.pos.0 > text.len() {
+                if self.pos.0 > document.line_len(self.pos.1) {
                     panic!("Cursor is too far into a line!");
                 }
-                
-                let string : String = text.drain(self.pos.0..).collect();
-                // update the 'drained' string.
-                *glyphs = State::batch_read_string(glyph_brush, self.font_size, self.screen_size, text);
-                *breaks = State::wrap_line(glyphs,text); // update line break indices.
-
-                // calculate the new line's line breaks.
-                let line_glyphs = State::batch_read_string(glyph_brush, self.font_size, self.screen_size, &string);
-                let new_breaks = State::wrap_line(&line_glyphs,&string);
-                let new_line = Line{ text: string, breaks : new_breaks, glyphs: line_glyphs };
-                lines.insert(self.pos.1+1, new_line);
-                self.pos.1 += 1;
-                self.pos.0 = 0;
+
+                Some( (index..index, String::from("\n")) )
             },
             '\u{8}' => { // backspace
-                let mut update_line = self.pos.1;
                 if self.pos.0 > 0 {
-                    text.remove(if self.pos.0 == 0 {self.pos.0} else {self.pos.0-1} );
-                    self.pos.0 -= 1;
-                } else if self.pos.1 > 0 {
-                    // Copy the remaining text from this line and copy to the last line.
-                    let Line{ text, .. } = lines.remove(self.pos.1);
-                    let len = lines[self.pos.1-1].text.len();
-
-                    lines[self.pos.1-1].text.insert_str( len, text.as_str());
-                    self.pos.0 = len;
-                    
-                    self.pos.1 -= 1;
-                    
-                    update_line = self.pos.1;
+                    let start = prev_grapheme(&document.line(self.pos.1), self.pos.0);
+                    Some( (index - (self.pos.0 - start)..index, String::new()) )
+                } else if index > 0 {
+                    // at the start of a line, this removes the '\n' and joins it onto the last line.
+                    Some( (index-1..index, String::new()) )
+                } else {
+                    None
                 }
-                // update
-                let line_glyphs = State::batch_read_string(glyph_brush, self.font_size, self.screen_size, &lines[update_line].text);
-                lines[update_line].breaks = State::wrap_line(&line_glyphs,&lines[update_line].text); // update line break indices.
-                lines[update_line].glyphs = line_glyphs;
             }
             '\t' => {
-
+                let column = visual_column(&document.line(self.pos.1), self.pos.0, document.indent.width);
+                Some( (index..index, document.indent.text(column)) )
             }
-            character if character.is_control() == false => {
-                text.insert(self.pos.0,character);
-                // TODO: be smarter, don't totally recalcuate everything all the time.
-                let line_glyphs = State::batch_read_string(glyph_brush, self.font_size, self.screen_size, text);
-                *breaks = State::wrap_line(&line_glyphs,text); // update line break indices.
-                *glyphs = line_glyphs;
-                
-                self.pos.0 += 1;
-            }, // unwrap should be safe.
-            _ => {}
+            character if !character.is_control() => {
+                Some( (index..index, character.to_string()) )
+            },
+            _ => None
         }
     }
+
+    // Like insert_text, but for the ways of deleting that aren't a character.
+    //  At the end or start of a line, these take the '\n' and join the lines.
+    pub fn delete_text(&self, document : &Document, deletion : Deletion) -> Option<(Range<usize>,String)> {
+        if deletion == Deletion::Line {
+            return Some( (document.whole_lines(self.selected_lines()), String::new()) );
+        }
+
+        // everything else just deletes the selection when there is one.
+        if self.has_selection() {
+            return Some( (self.selection_range(document), String::new()) );
+        }
+
+        let index = document.pos_to_byte(self.pos);
+        let line = document.line(self.pos.1);
+        let range = match deletion {
+            Deletion::Forward | Deletion::WordRight if self.pos.0 < line.len() => {
+                let end = if deletion == Deletion::Forward { next_grapheme(&line, self.pos.0) } else { next_word_boundary(&line, self.pos.0) };
+                index..index + end - self.pos.0
+            }
+            Deletion::WordLeft if self.pos.0 > 0 => {
+                index - (self.pos.0 - prev_word_boundary(&line, self.pos.0))..index
+            }
+            // at the end of a line, the next one gets pulled up.
+            Deletion::Forward | Deletion::WordRight if index < document.len_bytes() => index..index+1,
+            Deletion::WordLeft if index > 0 => index-1..index,
+            _ => return None
+        };
+        Some( (range, String::new()) )
+    }
 }
@@ -0,0 +1,731 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Write;
+use std::ops::{Range, RangeInclusive};
+use ropey::Rope;
+use super::layout::{Line, TextLayout};
+
+// How a file is indented. Tab inserts a real tab, or spaces up to the next tab stop.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct Indent {
+    pub soft : bool, // spaces instead of tabs
+    pub width : usize // columns between tab stops
+}
+impl Default for Indent {
+    fn default() -> Self {
+        Indent { soft : true, width : 4 }
+    }
+}
+impl Indent {
+    // Guess from the first lines of a file. Tabs if most indented lines start with one,
+    //  otherwise spaces, as wide as the narrowest indent.
+    fn detect(text : &Rope) -> Self {
+        let (mut tabs, mut spaces) = (0,0);
+        let mut narrowest = usize::MAX;
+        for line in text.lines().take(1000) {
+            match line.chars().next() {
+                Some('\t') => tabs += 1,
+                Some(' ') => {
+                    let width = line.chars().take_while(|character| *character == ' ').count();
+                    if width >= 2 {
+                        spaces += 1;
+                        narrowest = narrowest.min(width);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if tabs > spaces {
+            Indent { soft : false, ..Indent::default() }
+        } else if spaces > 0 {
+            Indent { soft : true, width : narrowest.min(8) }
+        } else {
+            Indent::default()
+        }
+    }
+
+    // One level of indentation, for a cursor at this visual column.
+    pub fn text(&self, column : usize) -> String {
+        if self.soft {
+            " ".repeat(self.width - column % self.width)
+        } else {
+            String::from("\t")
+        }
+    }
+}
+
+// What goes between lines in the file. In the buffer it is always '\n'.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr
+}
+impl LineEnding {
+    // Whichever one the file uses most, and whether it uses any others too. Files without any get '\n'.
+    fn detect(text : &str) -> (Self, bool) {
+        let crlf = text.matches("\r\n").count();
+        let counts = [(LineEnding::Lf, text.matches('\n').count() - crlf), (LineEnding::CrLf, crlf), (LineEnding::Cr, text.matches('\r').count() - crlf)];
+        let mut line_ending = LineEnding::Lf;
+        let mut most = 0;
+        for (kind,count) in counts {
+            if count > most {
+                (line_ending, most) = (kind, count);
+            }
+        }
+        (line_ending, counts.iter().filter(|(_,count)| *count > 0).count() > 1)
+    }
+
+    pub fn text(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r"
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR"
+        }
+    }
+}
+
+// How the text is stored in the file.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum Encoding {
+    Utf8,
+    Utf8Bom, // with the byte order mark some windows programs put at the start
+    Utf16Le, // both kinds of utf-16 are recognised by their byte order mark, or by the nuls in mostly ascii text, and always saved with the mark
+    Utf16Be,
+    Windows1252 // latin-1 plus some punctuation, what old windows files are usually in. Only a guess, when a file isn't utf-8
+}
+
+// What bytes 0x80 to 0x9F are in windows-1252. The five it leaves out are read like latin-1 does, so every byte reads as something and writes back the same.
+const WINDOWS_1252 : [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}', '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}', '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}'
+];
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf8Bom => "UTF-8 BOM",
+            Encoding::Utf16Le => "UTF-16 LE",
+            Encoding::Utf16Be => "UTF-16 BE",
+            Encoding::Windows1252 => "Windows-1252"
+        }
+    }
+
+    fn bom(&self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8Bom => b"\xEF\xBB\xBF",
+            Encoding::Utf16Le => b"\xFF\xFE",
+            Encoding::Utf16Be => b"\xFE\xFF",
+            Encoding::Utf8 | Encoding::Windows1252 => b""
+        }
+    }
+
+    // Works out what a file is in and reads it. Anything that couldn't be read is replaced with '\u{FFFD}',
+    //  and the bool says if that happened, or if it doesn't look like text at all, since saving it would lose whatever was there.
+    fn decode(bytes : &[u8]) -> (String, Encoding, bool) {
+        let (encoding,bytes) = match [Encoding::Utf8Bom, Encoding::Utf16Le, Encoding::Utf16Be].into_iter().find(|encoding| bytes.starts_with(encoding.bom())) {
+            Some(encoding) => (encoding, &bytes[encoding.bom().len()..]),
+            None => (Encoding::guess_utf16(bytes).unwrap_or_else(|| Encoding::guess_8_bit(bytes)), bytes)
+        };
+
+        let (text,lossy) : (String,bool) = match encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let text = String::from_utf8_lossy(bytes);
+                let lossy = matches!(text, Cow::Owned(_));
+                (text.into_owned(), lossy)
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let units = bytes.chunks_exact(2).map(|pair| match encoding {
+                    Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                    _ => u16::from_be_bytes([pair[0], pair[1]])
+                });
+                let mut lossy = !bytes.len().is_multiple_of(2); // half a character at the end
+                let text = char::decode_utf16(units).map(|c| c.unwrap_or_else(|_| { lossy = true; char::REPLACEMENT_CHARACTER })).collect();
+                (text, lossy)
+            }
+            Encoding::Windows1252 => {
+                // windows-1252 can read anything.
+                (bytes.iter().map(|&byte| match byte {
+                    0x80..=0x9F => WINDOWS_1252[byte as usize - 0x80],
+                    _ => byte as char
+                }).collect(), false)
+            }
+        };
+        // whatever it was read as, nul characters mean it isn't text, like a program or a picture.
+        let lossy = lossy || text.contains('\0');
+        (text, encoding, lossy)
+    }
+
+    // utf-8, even with a few bad bytes in it like a damaged file, which then opens read only. The accented
+    //  letters of windows-1252 are never valid utf-8 on their own, so it's only that when most of them aren't.
+    fn guess_8_bit(bytes : &[u8]) -> Encoding {
+        let (mut good, mut bad) = (0,0);
+        for chunk in bytes.utf8_chunks() {
+            good += chunk.valid().chars().filter(|c| !c.is_ascii()).count();
+            bad += !chunk.invalid().is_empty() as usize;
+        }
+        if bad == 0 || good >= bad { Encoding::Utf8 } else { Encoding::Windows1252 }
+    }
+
+    // utf-16 without a byte order mark. Mostly ascii text has a nul in every other byte,
+    //  after each character in little endian and before it in big endian, and none in between.
+    fn guess_utf16(bytes : &[u8]) -> Option<Encoding> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(2) {
+            return None;
+        }
+        let sample = &bytes[..bytes.len().min(1024)];
+        let nuls = |start : usize| sample.iter().skip(start).step_by(2).filter(|&&byte| byte == 0).count();
+        let (even,odd) = (nuls(0), nuls(1));
+        let half = sample.len() / 4;
+        if even == 0 && odd > half {
+            Some(Encoding::Utf16Le)
+        } else if odd == 0 && even > half {
+            Some(Encoding::Utf16Be)
+        } else {
+            None
+        }
+    }
+
+    // The first character that can't be saved in this encoding, if there is one.
+    fn unencodable(&self, text : &str) -> Option<char> {
+        match self {
+            Encoding::Windows1252 => text.chars().find(|&c| Encoding::encode_1252(c).is_none()),
+            _ => None
+        }
+    }
+
+    fn encode_1252(c : char) -> Option<u8> {
+        match c as u32 {
+            0..=0x7F | 0xA0..=0xFF => Some(c as u8),
+            _ => WINDOWS_1252.iter().position(|&other| other == c).map(|index| 0x80 + index as u8)
+        }
+    }
+
+    // Check with unencodable first, anything that doesn't fit comes out as '?'.
+    fn encode<'a>(&self, text : &'a str) -> Cow<'a, [u8]> {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => text.as_bytes().into(),
+            Encoding::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Encoding::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Encoding::Windows1252 => text.chars().map(|c| Encoding::encode_1252(c).unwrap_or(b'?')).collect()
+        }
+    }
+}
+
+// Why a save didn't make it to disk.
+#[derive(Debug)]
+pub enum SaveError {
+    Open(std::io::Error), // couldn't open the file to write it, like when it's read only
+    Write(std::io::Error), // opened, but stopped partway through, like when the disk is full
+    Encode(Encoding, char), // the text has a character the file's encoding can't store
+    ReadOnly // some of the file couldn't be read, so saving would lose it
+}
+impl fmt::Display for SaveError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SaveError::Open(e) => write!(f, "Couldn't open the file to save : {}", e),
+            SaveError::Write(e) => write!(f, "Couldn't finish saving, the file may be incomplete : {}", e),
+            SaveError::Encode(encoding, c) => write!(f, "Couldn't save, {} can't store '{}'. Pick another encoding", encoding.name(), c),
+            SaveError::ReadOnly => write!(f, "Opened read only, some of the file couldn't be read and saving would lose it")
+        }
+    }
+}
+impl std::error::Error for SaveError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SaveError::Open(e) | SaveError::Write(e) => Some(e),
+            SaveError::Encode(..) | SaveError::ReadOnly => None
+        }
+    }
+}
+
+// The text of a file. Whether it matches what is on disk is up to the editor's history.
+// Lines are split by '\n' only, and positions are (byte index into the line, line).
+pub struct Document {
+    pub file_name : String,
+    text : Rope,
+    pub lines : Vec<Line>, // layout of each line of text, in the same order.
+    pub indent : Indent,
+    pub line_ending : LineEnding,
+    pub final_newline : bool, // whether the file ends with a line ending, which isn't in the buffer
+    pub encoding : Encoding,
+    read_only : Option<Vec<u8>>, // the file as it was, when it couldn't be read without losing some of it
+    mixed_line_endings : bool // the file had more than one kind, and saving will make them all line_ending
+}
+
+impl Document {
+    // Read a file. It still needs to be laid out with calculate before it can be drawn.
+    pub fn open(file_name : String) -> Self {
+        match std::fs::read(&file_name) {
+            Ok(bytes) => Document::from_bytes(file_name, &bytes),
+            Err(e ) => {
+                //TODO: Handle some of the errors from e
+                if let std::io::ErrorKind::NotFound = e.kind() {
+                    Document { file_name, text : Rope::new(), lines : vec![], indent : Indent::default(), line_ending : LineEnding::Lf, final_newline : true, encoding : Encoding::Utf8, read_only : None, mixed_line_endings : false }
+                } else {
+                    panic!("Error opening file! : {}",e);
+                }
+            },
+        }
+    }
+
+    // The text in what would be the contents of a file, like after editing it as hex.
+    pub fn from_bytes(file_name : String, bytes : &[u8]) -> Self {
+        // if the file contains bad text, show what could be read but never save over it.
+        let (text,encoding,lossy) = Encoding::decode(bytes);
+        let read_only = lossy.then(|| bytes.to_vec());
+        let (line_ending,mixed) = LineEnding::detect(&text);
+        // a read only file is saved as it was anyway.
+        let mixed_line_endings = mixed && read_only.is_none();
+
+        // the buffer only has '\n' between lines, and like before, a newline at the very end isn't a line.
+        let mut text = if text.contains('\r') { text.replace("\r\n", "\n").replace('\r', "\n") } else { text };
+        let final_newline = text.ends_with('\n');
+        if final_newline {
+            text.pop();
+        }
+        let text = Rope::from_str(&text);
+
+        let indent = Indent::detect(&text);
+        Document { file_name, text, lines : vec![], indent, line_ending, final_newline, encoding, read_only, mixed_line_endings }
+    }
+
+    // Whether the file had different line endings on different lines. The buffer can't tell them
+    //  apart, so they all become line_ending, and the document counts as modified.
+    pub fn mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    // Lay every line out again, like after the window changes size.
+    pub fn calculate(&mut self, layout : &TextLayout) {
+        self.lines = (0..self.len_lines()).map(|i| Line::new(&self.line(i), layout)).collect();
+    }
+
+    // Always at least one, an empty document has one empty line.
+    pub fn len_lines(&self) -> usize {
+        self.text.len_lines()
+    }
+
+    // The text of a line, without its '\n'.
+    pub fn line(&self, index : usize) -> Cow<'_, str> {
+        let slice = self.text.line(index);
+        let len = slice.len_chars();
+        if len > 0 && slice.char(len-1) == '\n' {
+            slice.slice(..len-1).into()
+        } else {
+            slice.into()
+        }
+    }
+
+    // Length of a line in bytes, without its '\n'.
+    pub fn line_len(&self, index : usize) -> usize {
+        self.lines[index].text_len()
+    }
+
+    // Total length in bytes.
+    pub fn len_bytes(&self) -> usize {
+        self.text.len_bytes()
+    }
+
+    pub fn pos_to_byte(&self, pos : (usize,usize)) -> usize {
+        self.text.line_to_byte(pos.1) + pos.0
+    }
+
+    pub fn byte_to_pos(&self, byte : usize) -> (usize,usize) {
+        let line = self.text.byte_to_line(byte);
+        (byte - self.text.line_to_byte(line), line)
+    }
+
+    // The bytes of some lines with one of the '\n's around them, so taking them out doesn't leave an empty line.
+    //  The last line takes the '\n' before it instead of after.
+    pub fn whole_lines(&self, lines : RangeInclusive<usize>) -> Range<usize> {
+        let (first,last) = (*lines.start(), *lines.end());
+        if last+1 < self.len_lines() {
+            self.pos_to_byte((0,first))..self.pos_to_byte((0,last+1))
+        } else if first > 0 {
+            self.pos_to_byte((0,first))-1..self.len_bytes()
+        } else {
+            0..self.len_bytes()
+        }
+    }
+
+    // How many characters are in a byte range, without copying them out.
+    pub fn count_chars(&self, range : Range<usize>) -> usize {
+        self.text.byte_to_char(range.end) - self.text.byte_to_char(range.start)
+    }
+
+    // Copy some of the text out, by byte range.
+    pub fn slice(&self, range : Range<usize>) -> String {
+        let start = self.text.byte_to_char(range.start);
+        let end = self.text.byte_to_char(range.end);
+        self.text.slice(start..end).to_string()
+    }
+
+    // The next place needle shows up, at or after from. Wraps around to the top of the document.
+    pub fn find(&self, needle : &str, from : usize) -> Option<usize> {
+        if needle.is_empty() {
+            return None;
+        }
+        let text : Cow<'_, str> = self.text.slice(..).into();
+        match text[from..].find(needle) {
+            Some(index) => Some(from + index),
+            None => text.find(needle)
+        }
+    }
+
+    // Replace a range of bytes with some text, and lay out the lines it touched.
+    pub fn replace(&mut self, range : Range<usize>, text : &str, layout : &TextLayout) {
+        let first = self.text.byte_to_line(range.start);
+        let last = self.text.byte_to_line(range.end);
+
+        let start = self.text.byte_to_char(range.start);
+        let end = self.text.byte_to_char(range.end);
+        self.text.remove(start..end);
+        self.text.insert(start, text);
+
+        // only the lines between the start of the edit and the end of the new text changed.
+        let new_last = self.text.byte_to_line(range.start + text.len());
+        let new_lines : Vec<Line> = (first..=new_last).map(|i| Line::new(&self.line(i), layout)).collect();
+        self.lines.splice(first..=last, new_lines);
+    }
+
+    // Whether the file couldn't be read without losing some of it. Then it can't be edited as text, or saved.
+    pub fn read_only(&self) -> bool {
+        self.read_only.is_some()
+    }
+
+    pub fn save_file(&mut self) -> Result<(), SaveError> {
+        if self.read_only() {
+            return Err(SaveError::ReadOnly);
+        }
+        // find out before touching the file, so it doesn't end up half written.
+        if let Some(c) = self.text.chunks().find_map(|chunk| self.encoding.unencodable(chunk)) {
+            return Err(SaveError::Encode(self.encoding, c));
+        }
+        save(&self.file_name, |file| self.write_bytes(file))
+    }
+
+    // What saving would write. A read only document gives back the file it was read from untouched.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SaveError> {
+        if let Some(bytes) = &self.read_only {
+            return Ok(bytes.clone());
+        }
+        if let Some(c) = self.text.chunks().find_map(|chunk| self.encoding.unencodable(chunk)) {
+            return Err(SaveError::Encode(self.encoding, c));
+        }
+        let mut bytes = vec![];
+        self.write_bytes(&mut bytes).map_err(SaveError::Write)?;
+        Ok(bytes)
+    }
+
+    // The whole document, the way it is stored on disk.
+    fn write_bytes(&self, file : &mut dyn Write) -> std::io::Result<()> {
+        file.write_all(self.encoding.bom())?;
+        for chunk in self.text.chunks() {
+            let chunk = match self.line_ending {
+                LineEnding::Lf => Cow::Borrowed(chunk),
+                ending => Cow::Owned(chunk.replace('\n', ending.text()))
+            };
+            file.write_all(&self.encoding.encode(&chunk))?;
+        }
+        if self.final_newline {
+            file.write_all(&self.encoding.encode(self.line_ending.text()))?;
+        }
+        Ok(())
+    }
+}
+
+// Save by writing a new file next to the old one, then swapping it in, so a crash halfway
+//  through leaves the old file alone. Writes over the old file directly if there's no
+//  room to make a new one, like in a folder we can't write to.
+pub(crate) fn save(file_name : &str, contents : impl Fn(&mut dyn Write) -> std::io::Result<()>) -> Result<(), SaveError> {
+    // save to what a symlink points at, so the link stays a link.
+    let path = std::fs::canonicalize(file_name).unwrap_or_else(|_| std::path::PathBuf::from(file_name));
+    let original = std::fs::metadata(&path).ok();
+    // replacing a file only takes being allowed to write to its folder, so check we could write to the file itself.
+    if let Some(original) = &original {
+        if original.permissions().readonly() {
+            return Err(SaveError::Open(std::io::ErrorKind::PermissionDenied.into()));
+        }
+        std::fs::OpenOptions::new().write(true).open(&path).map_err(SaveError::Open)?;
+    }
+
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temp_path = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(original) = &original {
+        // nobody gets to read it that couldn't read the original, even before it's finished.
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(original.permissions().mode() & 0o7777);
+    }
+    let temp = match options.open(&temp_path) {
+        Ok(temp) => temp,
+        // somewhere we can write the file but not next to it, like a directory we don't own.
+        Err(e) if writable_in_place(&e, false) => return save_in_place(&path, &contents),
+        Err(e) => return Err(SaveError::Open(e))
+    };
+
+    // keep who owns it and who can read it, as far as we're allowed. The umask may have taken some permissions away.
+    let kept = match &original {
+        Some(original) => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                let _ = std::os::unix::fs::fchown(&temp, Some(original.uid()), Some(original.gid()));
+            }
+            temp.set_permissions(original.permissions()).map_err(SaveError::Write)
+        }
+        None => Ok(())
+    };
+    if let Err(e) = kept.and_then(|_| write_file(temp, &contents)) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
+    }
+
+    if let Err(e) = std::fs::rename(&temp_path, &path) {
+        let _ = std::fs::remove_file(&temp_path);
+        // some files can't be replaced, only written to, like ones mounted into a container.
+        return if writable_in_place(&e, true) { save_in_place(&path, &contents) } else { Err(SaveError::Write(e)) };
+    }
+    // make the rename itself stick too.
+    #[cfg(unix)]
+    if let Some(dir) = path.parent().and_then(|dir| std::fs::File::open(dir).ok()) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+// Whether a failed save through a temp file might still work by writing over the file itself.
+//  Anything else, like a full disk, would only go wrong again and take the original with it.
+fn writable_in_place(e : &std::io::Error, renaming : bool) -> bool {
+    use std::io::ErrorKind;
+    match e.kind() {
+        ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => true,
+        // a mount point, or a file on a different device than its directory.
+        ErrorKind::CrossesDevices | ErrorKind::ResourceBusy => renaming,
+        _ => false
+    }
+}
+
+// The old way, straight over the top of the file.
+fn save_in_place(path : &std::path::Path, contents : &dyn Fn(&mut dyn Write) -> std::io::Result<()>) -> Result<(), SaveError> {
+    //  Like Open("file", 'w') in C, I think.
+    let file = std::fs::OpenOptions::new().write(true).truncate(true).create(true).open(path).map_err(SaveError::Open)?;
+    write_file(file, contents)
+}
+
+fn write_file(file : std::fs::File, contents : &dyn Fn(&mut dyn Write) -> std::io::Result<()>) -> Result<(), SaveError> {
+    let mut file = std::io::BufWriter::new(file);
+    contents(&mut file).map_err(SaveError::Write)?;
+    // a full disk might only show up here, when the last of it gets written.
+    file.into_inner().map_err(|e| SaveError::Write(e.into_error()))?.sync_all().map_err(SaveError::Write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn indent(text : &str) -> Indent {
+        Document::from_bytes(String::from("test"), text.as_bytes()).indent
+    }
+
+    fn round_trip(bytes : &[u8]) -> Document {
+        let document = Document::from_bytes(String::from("test"), bytes);
+        assert_eq!(document.to_bytes().unwrap(), bytes);
+        document
+    }
+
+    #[test]
+    fn line_endings_round_trip() {
+        for bytes in [&b"a\nb\n"[..], b"a\r\nb\r\n", b"a\rb\r", b"a\nb", b"a\r\nb", b"", b"\n", b"\r\n\r\n"] {
+            let document = round_trip(bytes);
+            assert!(!document.mixed_line_endings());
+            assert_eq!(document.final_newline, bytes.ends_with(b"\n") || bytes.ends_with(b"\r"));
+        }
+        assert_eq!(round_trip(b"a\r\nb").line_ending, LineEnding::CrLf);
+        assert_eq!(round_trip(b"a\rb").line_ending, LineEnding::Cr);
+        assert_eq!(round_trip(b"ab").line_ending, LineEnding::Lf);
+    }
+
+    #[test]
+    fn converting_line_endings() {
+        let mut document = Document::from_bytes(String::from("test"), b"a\r\nb");
+        assert_eq!(document.len_lines(), 2);
+        document.line_ending = LineEnding::Lf;
+        document.final_newline = true;
+        assert_eq!(document.to_bytes().unwrap(), b"a\nb\n");
+    }
+
+    #[test]
+    fn mixed_line_endings() {
+        // saved with whichever there is most of.
+        let document = Document::from_bytes(String::from("test"), b"a\r\nb\nc\rd\r\n");
+        assert!(document.mixed_line_endings());
+        assert_eq!(document.line_ending, LineEnding::CrLf);
+        assert_eq!(document.len_lines(), 4);
+
+        // a stray '\r' counts too.
+        let document = Document::from_bytes(String::from("test"), b"a\nb\rc\n");
+        assert!(document.mixed_line_endings());
+        assert_eq!(document.line_ending, LineEnding::Lf);
+    }
+
+    #[test]
+    fn encodings_round_trip() {
+        assert_eq!(round_trip(b"plain \xE2\x82\xAC").encoding, Encoding::Utf8);
+        assert_eq!(round_trip(b"\xEF\xBB\xBFbom").encoding, Encoding::Utf8Bom);
+        assert_eq!(round_trip(b"\xFF\xFEh\0i\0").encoding, Encoding::Utf16Le);
+        assert_eq!(round_trip(b"\xFE\xFF\0h\0i").encoding, Encoding::Utf16Be);
+        // including the five bytes windows-1252 leaves out.
+        let document = round_trip(b"caf\xE9 \x80 \x81\x8D\x8F\x90\x9D");
+        assert_eq!(document.encoding, Encoding::Windows1252);
+        assert!(document.text.to_string().starts_with("café €"));
+        assert!(!document.read_only());
+    }
+
+    #[test]
+    fn damaged_utf8_is_read_only() {
+        let document = round_trip(b"na\xC3\xAFve caf\xC3\xA9 \xFF");
+        assert_eq!(document.encoding, Encoding::Utf8);
+        assert!(document.read_only());
+        assert_eq!(document.text.to_string(), "naïve café \u{FFFD}");
+
+        // more of it bad than good is more likely windows-1252.
+        let document = round_trip(b"na\xEFve caf\xE9 \xC3\xA9");
+        assert_eq!(document.encoding, Encoding::Windows1252);
+        assert!(!document.read_only());
+    }
+
+    #[test]
+    fn utf16_without_bom() {
+        let le : Vec<u8> = "hello\nworld".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let document = Document::from_bytes(String::from("test"), &le);
+        assert_eq!(document.encoding, Encoding::Utf16Le);
+        assert_eq!(document.text.to_string(), "hello\nworld");
+        assert!(!document.read_only());
+
+        let be : Vec<u8> = "hi".encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(Document::from_bytes(String::from("test"), &be).encoding, Encoding::Utf16Be);
+    }
+
+    #[test]
+    fn not_text_is_read_only() {
+        // valid utf-8, but not text.
+        let document = round_trip(b"\x00\x01\x02ELF\x00");
+        assert!(document.read_only());
+        // half a utf-16 character, and an unpaired surrogate.
+        assert!(round_trip(b"\xFF\xFEh\0i").read_only());
+        assert!(round_trip(b"\xFF\xFE\x00\xD8").read_only());
+    }
+
+    #[test]
+    fn encode_checks_what_fits() {
+        let mut document = Document::from_bytes(String::from("test"), "snowman \u{2603}".as_bytes());
+        document.encoding = Encoding::Windows1252;
+        assert!(matches!(document.to_bytes(), Err(SaveError::Encode(Encoding::Windows1252, '\u{2603}'))));
+        document.encoding = Encoding::Utf16Be;
+        assert_eq!(document.to_bytes().unwrap()[..4], [0xFE,0xFF,0,b's']);
+    }
+
+    #[test]
+    fn detects_indentation() {
+        assert_eq!(indent("a\n\tb\n\tc\n  d"), Indent { soft : false, width : 4 });
+        assert_eq!(indent("a\n  b\n    c"), Indent { soft : true, width : 2 });
+        assert_eq!(indent("a\n b"), Indent::default());
+        assert_eq!(Indent { soft : true, width : 4 }.text(1), "   ");
+    }
+
+    // A folder of its own for each test, cleaned up after.
+    struct TempDir(std::path::PathBuf);
+    impl TempDir {
+        fn new(name : &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("manip-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+        fn file(&self, name : &str) -> String {
+            self.0.join(name).to_string_lossy().into_owned()
+        }
+        fn files(&self) -> usize {
+            std::fs::read_dir(&self.0).unwrap().count()
+        }
+    }
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn save_replaces_the_file() {
+        let dir = TempDir::new("save");
+        let file = dir.file("a.txt");
+        std::fs::write(&file, "old\n").unwrap();
+        let mut document = Document::open(file.clone());
+        document.text = Rope::from_str("new");
+        document.save_file().unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), b"new\n");
+        // and the temp file is gone.
+        assert_eq!(dir.files(), 1);
+
+        // a file that doesn't exist yet.
+        let mut document = Document::open(dir.file("b.txt"));
+        document.save_file().unwrap();
+        assert_eq!(std::fs::read(dir.file("b.txt")).unwrap(), b"\n");
+        assert_eq!(dir.files(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_permissions_and_links() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = TempDir::new("metadata");
+        // wider than the usual umask lets new files be, and narrower.
+        for mode in [0o666, 0o640, 0o600] {
+            let file = dir.file(&format!("{:o}", mode));
+            std::fs::write(&file, "a").unwrap();
+            std::fs::set_permissions(&file, std::fs::Permissions::from_mode(mode)).unwrap();
+            Document::open(file.clone()).save_file().unwrap();
+            assert_eq!(std::fs::metadata(&file).unwrap().permissions().mode() & 0o777, mode);
+        }
+
+        let target = dir.file("target");
+        let link = dir.file("link");
+        std::fs::write(&target, "a").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        let mut document = Document::open(link.clone());
+        document.text = Rope::from_str("b");
+        document.save_file().unwrap();
+        assert!(std::fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(std::fs::read(&target).unwrap(), b"b");
+    }
+
+    #[test]
+    fn save_refuses_read_only_files() {
+        let dir = TempDir::new("readonly");
+        let file = dir.file("a.txt");
+        std::fs::write(&file, "old").unwrap();
+        let mut permissions = std::fs::metadata(&file).unwrap().permissions();
+        permissions.set_readonly(true);
+        std::fs::set_permissions(&file, permissions).unwrap();
+
+        let mut document = Document::open(file.clone());
+        document.text = Rope::from_str("new");
+        assert!(matches!(document.save_file(), Err(SaveError::Open(e)) if e.kind() == std::io::ErrorKind::PermissionDenied));
+        assert_eq!(std::fs::read(&file).unwrap(), b"old");
+        assert_eq!(dir.files(), 1);
+    }
+}
@@ -0,0 +1,1234 @@
+// The editing core. Everything in here works without a window, the frontend in
+//  main.rs only draws what is in here and feeds it input.
+use std::ops::Range;
+use super::clipboard::{Clipboard, ClipboardKind, MemoryClipboard};
+use super::cursor::{self, Cursor, CursorMovement, Deletion};
+use super::document::{Document, Encoding, Indent, LineEnding, SaveError};
+use super::history::{Change, EditKind, History};
+use super::layout::{Metrics, TextLayout, Wrap};
+use unicode_segmentation::UnicodeSegmentation;
+
+// What a click selects. Double click is a word, triple click is a line.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum SelectUnit {
+    Character,
+    Word,
+    Line
+}
+
+pub struct Editor {
+    pub document : Document,
+    pub cursors : Vec<Cursor>,
+    pub layout : TextLayout,
+    history : History,
+    // the encoding, line ending and final newline when the file was opened or last saved. None when what's
+    //  on disk differs in some way changing those back can't undo, like mixed line endings that were made the same.
+    saved_format : Option<(Encoding,LineEnding,bool)>,
+    clipboard : Box<dyn Clipboard>,
+    drag : Option<(Cursor,SelectUnit)>, // what the mouse first selected, while it is held down.
+
+    pub line_numbers : LineNumbers, // change with set_line_numbers, the text has to make room
+    gutter : f32, // how wide the line numbers are, in pixels. The text starts after them.
+    pub ruler : bool, // draw a line down the column text wraps at
+    pub logical_lines : bool, // up and down skip over whole wrapped lines, instead of going a row at a time
+    pub scroll_margin : usize, // rows kept between the cursor and the top or bottom when scrolling to it
+    pub smooth_scroll : bool, // the wheel glides there over a few frames, instead of jumping
+    pub wheel_lines : f64, // how far one notch of the wheel scrolls
+    pub scroll : f64, // in pixels
+    scroll_target : f64, // where smooth scrolling is headed. The same as scroll when it isn't moving.
+    pub scroll_x : f64, // in pixels, only when lines don't wrap
+    size : (f32,f32) // of the gutter and text together, in pixels
+}
+
+// What the gutter down the left shows.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    Relative // how far each line is from the cursor's, which shows its own number
+}
+
+// Everything the status bar shows.
+pub struct Status {
+    pub line : usize, // of the newest cursor, counting from 1 like the gutter
+    pub column : usize, // in graphemes, also from 1
+    pub selected : usize, // characters selected, by every cursor together
+    pub cursors : usize,
+    pub lines : usize,
+    pub encoding : Encoding,
+    pub line_ending : LineEnding,
+    pub final_newline : bool,
+    pub indent : Indent,
+    pub read_only : bool,
+    pub mode : String
+}
+
+// Where an offset ends up after some edits (sorted, not overlapping) are applied.
+//  Something at the exact spot text is inserted ends up after it.
+fn map_offset(offset : usize, edits : &[(Range<usize>,String)]) -> usize {
+    let mut delta : isize = 0;
+    for (range,text) in edits {
+        if offset < range.start {
+            break;
+        }
+        if offset >= range.end {
+            delta += text.len() as isize - range.len() as isize;
+        } else {
+            // the text it was in got replaced.
+            return (range.start as isize + delta) as usize + text.len();
+        }
+    }
+    (offset as isize + delta) as usize
+}
+
+impl Editor {
+    pub fn new(file_name : String, metrics : Box<dyn Metrics>, screen_size : (u32,u32)) -> Self {
+        let mut layout = TextLayout::new(metrics, screen_size);
+        let document = Document::open(file_name);
+        layout.tab_width = document.indent.width;
+        let saved_format = (!document.mixed_line_endings()).then(|| Editor::format_of(&document));
+        let clipboard = Box::new(MemoryClipboard::default());
+        let mut editor = Editor { document, cursors : vec![Cursor::new((0,0))], layout, history : History::new(), saved_format, clipboard, drag : None, line_numbers : LineNumbers::Absolute, gutter : 0.0, ruler : true, logical_lines : false, scroll_margin : 3, smooth_scroll : true, wheel_lines : 3.0, scroll : 0.0, scroll_target : 0.0, scroll_x : 0.0, size : (screen_size.0 as f32, screen_size.1 as f32) };
+        editor.gutter = editor.gutter_width();
+        editor.layout.resize(editor.text_size());
+        editor.document.calculate(&editor.layout);
+        editor
+    }
+
+    // Copy and paste through something else, like the system clipboard.
+    pub fn set_clipboard(&mut self, clipboard : Box<dyn Clipboard>) {
+        self.clipboard = clipboard;
+    }
+
+    // Why the last copy didn't make it to the clipboard, for the frontend to show.
+    pub fn clipboard_error(&mut self) -> Option<String> {
+        self.clipboard.take_error()
+    }
+
+    // Swap in another version of the file, like after editing it as hex. Undo starts over from
+    //  here, and modified is whether it differs from what was last saved. Line endings that
+    //  had to be made the same count as a change too.
+    pub fn set_document(&mut self, document : Document, modified : bool) {
+        self.document = document;
+        self.layout.tab_width = self.document.indent.width;
+        self.cursors = vec![Cursor::new((0,0))];
+        self.history = History::new();
+        self.saved_format = (!modified && !self.document.mixed_line_endings()).then(|| Editor::format_of(&self.document));
+        self.drag = None;
+        self.scroll = 0.0;
+        self.scroll_target = 0.0;
+        self.scroll_x = 0.0;
+        self.gutter = self.gutter_width();
+        self.layout.resize(self.text_size());
+        self.document.calculate(&self.layout);
+    }
+
+    // screen_size is the part of the window the gutter and text are drawn in.
+    pub fn resize(&mut self, screen_size : (u32,u32)) {
+        self.size = (screen_size.0 as f32, screen_size.1 as f32);
+        // recalculate word wrap
+        self.layout.resize(self.text_size());
+        self.document.calculate(&self.layout);
+        self.clamp_scroll();
+    }
+
+    // The part of the editor the text goes in, right of the gutter.
+    fn text_size(&self) -> (u32,u32) {
+        ((self.size.0 - self.gutter).max(0.0) as u32, self.size.1 as u32)
+    }
+
+    // Wide enough for the biggest line number, with a space either side.
+    fn gutter_width(&self) -> f32 {
+        if self.line_numbers == LineNumbers::Off {
+            return 0.0;
+        }
+        let digits = self.document.len_lines().to_string().len();
+        self.layout.column_x(digits + 2)
+    }
+
+    // Where the text starts, right of the gutter. Everything the editor hands out in pixels
+    //  (cursor_rect, selection_rects, ruler_x, hit_test) is measured from the gutter's left instead.
+    pub fn text_x(&self) -> f32 {
+        self.gutter
+    }
+
+    pub fn set_line_numbers(&mut self, line_numbers : LineNumbers) {
+        self.line_numbers = line_numbers;
+        self.update_gutter();
+    }
+
+    // Once the line count gains or loses a digit, the gutter changes width and the text has to wrap again.
+    fn update_gutter(&mut self) {
+        let gutter = self.gutter_width();
+        if gutter != self.gutter {
+            self.gutter = gutter;
+            self.layout.resize(self.text_size());
+            self.document.calculate(&self.layout);
+        }
+    }
+
+    // The line numbers on screen, and how far down the text area to draw each one. A line
+    //  wrapped over several rows only has its number on the first.
+    pub fn gutter_labels(&self) -> Vec<(String,i64)> {
+        if self.line_numbers == LineNumbers::Off {
+            return vec![];
+        }
+        let line_height = self.layout.line_height() as i64;
+        let current = self.cursors.last().unwrap().pos.1;
+        let mut labels = vec![];
+        let mut y = -self.scroll_offset();
+        for (i,line) in self.document.lines.iter().enumerate() {
+            if y > self.size.1 as i64 {
+                break;
+            }
+            if y + line.glyphs.len() as i64 * line_height >= 0 {
+                let number = match self.line_numbers {
+                    LineNumbers::Relative if i != current => i.abs_diff(current),
+                    _ => i + 1
+                };
+                labels.push( (number.to_string(), y) );
+            }
+            y += line.glyphs.len() as i64 * line_height;
+        }
+        labels
+    }
+
+    // Change how tab indents this file. Tabs already in it are drawn at the new width.
+    pub fn set_indent(&mut self, indent : Indent) {
+        self.document.indent = indent;
+        self.layout.tab_width = indent.width;
+        self.document.calculate(&self.layout);
+    }
+
+    // Wrap long lines, or let them run off the side of the window.
+    pub fn set_wrap(&mut self, wrap : Wrap) {
+        self.layout.wrap = wrap;
+        self.scroll_x = 0.0;
+        self.document.calculate(&self.layout);
+        self.scroll_to_cursor();
+    }
+
+    // Whether wrapped rows are indented to line up with the start of their line's text.
+    pub fn set_wrap_indent(&mut self, wrap_indent : bool) {
+        self.layout.wrap_indent = wrap_indent;
+        self.document.calculate(&self.layout);
+    }
+
+    // These change the file without touching the text, so there is nothing to undo, but it still needs
+    //  saving until they're changed back.
+    pub fn set_encoding(&mut self, encoding : Encoding) {
+        self.document.encoding = encoding;
+    }
+    pub fn set_line_ending(&mut self, line_ending : LineEnding) {
+        self.document.line_ending = line_ending;
+    }
+    pub fn set_final_newline(&mut self, final_newline : bool) {
+        self.document.final_newline = final_newline;
+    }
+
+    fn format_of(document : &Document) -> (Encoding,LineEnding,bool) {
+        (document.encoding, document.line_ending, document.final_newline)
+    }
+
+    pub fn status(&self) -> Status {
+        let cursor = self.cursors.last().unwrap();
+        let column = self.document.line(cursor.pos.1)[..cursor.pos.0].graphemes(true).count() + 1;
+        let selected = self.cursors.iter().map(|cursor| self.document.count_chars(cursor.selection_range(&self.document))).sum();
+        let mode = match self.layout.wrap {
+            Wrap::None => String::from("No wrap"),
+            Wrap::Window => String::from("Wrap"),
+            Wrap::Column(columns) => format!("Wrap {}", columns)
+        };
+        // only makes a difference when lines wrap.
+        let mode = if self.logical_lines && self.layout.wrap != Wrap::None { mode + ", by line" } else { mode };
+        Status {
+            line : cursor.pos.1 + 1, column, selected, cursors : self.cursors.len(), lines : self.document.len_lines(),
+            encoding : self.document.encoding, line_ending : self.document.line_ending, final_newline : self.document.final_newline, indent : self.document.indent, read_only : self.document.read_only(), mode
+        }
+    }
+
+    // Only counts as saved if all of it got written. Otherwise the document stays modified.
+    pub fn save_file(&mut self) -> Result<(), SaveError> {
+        self.document.save_file()?;
+        self.saved_format = Some(Editor::format_of(&self.document));
+        self.history.mark_saved();
+        Ok(())
+    }
+
+    // Has the document been modified since it was last saved?
+    pub fn modified(&self) -> bool {
+        self.history.is_modified() || self.saved_format != Some(Editor::format_of(&self.document))
+    }
+
+    // Replace ranges of bytes in the document, and move every cursor to stay with the text
+    //  around it. This is the only way the editor changes the document, so it can be undone.
+    pub fn edit(&mut self, edits : Vec<(Range<usize>,String)>, kind : EditKind) {
+        self.edit_then(edits, kind, |_| {});
+    }
+
+    // The same, with a chance to fix up where the cursors went before they're merged and remembered for undo.
+    fn edit_then(&mut self, mut edits : Vec<(Range<usize>,String)>, kind : EditKind, adjust : impl FnOnce(&mut [Cursor])) {
+        edits.sort_by_key(|(range,_)| (range.start, range.end));
+        // two cursors can ask for the same edit. Only do it once, and never let edits overlap.
+        edits.dedup_by(|a,b| a.0 == b.0);
+        let mut last_end = 0;
+        edits.retain(|(range,text)| {
+            let keep = range.start >= last_end && !(range.is_empty() && text.is_empty());
+            if keep {
+                last_end = range.end.max(last_end);
+            }
+            keep
+        });
+        // a read only document never changes, so there is nothing that could be lost by saving.
+        if edits.is_empty() || self.document.read_only() {
+            return;
+        }
+
+        // after an edit, up and down start from wherever the cursors are now. Undo puts them back that way too.
+        for cursor in &mut self.cursors {
+            cursor.goal = None;
+        }
+        let cursors_before = self.cursors.clone();
+        let offsets : Vec<(usize,usize)> = self.cursors.iter()
+            .map(|cursor| (self.document.pos_to_byte(cursor.pos), self.document.pos_to_byte(cursor.anchor))).collect();
+
+        // back to front, so the offsets of the edits that haven't happened yet stay right.
+        let mut changes = vec![];
+        for (range,text) in edits.iter().rev() {
+            let removed = self.document.slice(range.clone());
+            self.document.replace(range.clone(), text, &self.layout);
+            changes.push(Change { start : range.start, removed, inserted : text.clone() });
+        }
+
+        for (cursor,(pos,anchor)) in self.cursors.iter_mut().zip(offsets) {
+            cursor.pos = Editor::snap(&self.document, self.document.byte_to_pos(map_offset(pos, &edits)));
+            cursor.anchor = Editor::snap(&self.document, self.document.byte_to_pos(map_offset(anchor, &edits)));
+        }
+        adjust(&mut self.cursors);
+        self.merge_cursors();
+
+        self.history.record(changes, cursors_before, self.cursors.clone(), kind);
+        self.update_gutter();
+        self.scroll_to_cursor();
+    }
+
+    // Forward onto the next grapheme boundary, if an edit left pos inside a grapheme.
+    fn snap(document : &Document, pos : (usize,usize)) -> (usize,usize) {
+        (cursor::snap_to_grapheme(&document.line(pos.1), pos.0), pos.1)
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(transaction) = self.history.undo() {
+            // take the changes back in the opposite order they were made.
+            for change in transaction.changes.iter().rev() {
+                self.document.replace(change.start..change.start + change.inserted.len(), &change.removed, &self.layout);
+            }
+            self.cursors = transaction.cursors_before.clone();
+            self.update_gutter();
+            self.scroll_to_cursor();
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(transaction) = self.history.redo() {
+            for change in &transaction.changes {
+                self.document.replace(change.start..change.start + change.removed.len(), &change.inserted, &self.layout);
+            }
+            self.cursors = transaction.cursors_after.clone();
+            self.update_gutter();
+            self.scroll_to_cursor();
+        }
+    }
+
+    // How many rows fit in the text area, for page up and page down.
+    pub fn page_rows(&self) -> usize {
+        ((self.size.1 / self.layout.line_height()) as usize).max(1)
+    }
+
+    // select moves the cursors while keeping their anchors, like holding shift.
+    pub fn move_cursor(&mut self, direction : CursorMovement, select : bool) {
+        let direction = match direction {
+            CursorMovement::Up if self.logical_lines => CursorMovement::LineUp,
+            CursorMovement::Down if self.logical_lines => CursorMovement::LineDown,
+            direction => direction
+        };
+        for cursor in &mut self.cursors {
+            cursor.move_cursor(&self.document, direction, select);
+        }
+        // the view turns the page along with the cursors.
+        match direction {
+            CursorMovement::PageUp(rows) => self.scroll_by(-(rows as f64)),
+            CursorMovement::PageDown(rows) => self.scroll_by(rows as f64),
+            _ => {}
+        }
+        self.merge_cursors();
+        self.history.seal();
+        self.scroll_to_cursor();
+
+        if select {
+            if let Some(text) = self.selected_text() {
+                self.clipboard.set(ClipboardKind::Primary, text);
+            }
+        }
+    }
+
+    pub fn insert_cursor(&mut self, character : char) {
+        // the cursor is an index. backspace removes the character before the cursor.
+        let mut edits = vec![];
+        // selections that start at the very start of a line they indent, which should keep the new indentation in them.
+        let mut line_starts = vec![];
+        for (i,cursor) in self.cursors.iter().enumerate() {
+            // tab with more than one line selected indents all of them.
+            if character == '\t' && cursor.selected_lines().count() > 1 {
+                let indent = self.document.indent.text(0);
+                edits.extend(cursor.selected_lines().map(|line| {
+                    let start = self.document.pos_to_byte((0,line));
+                    (start..start, indent.clone())
+                }));
+                if cursor.selection().0.0 == 0 {
+                    line_starts.push(i);
+                }
+            } else {
+                edits.extend(cursor.insert_text(&self.document, character));
+            }
+        }
+        let kind = match character {
+            '\u{8}' => EditKind::Deleting,
+            character if character.is_control() => EditKind::Other,
+            _ => EditKind::Typing
+        };
+        // text put in right at a cursor goes before it, so put those back.
+        self.edit_then(edits, kind, |cursors| {
+            for i in line_starts {
+                let cursor = &mut cursors[i];
+                if (cursor.pos.1,cursor.pos.0) < (cursor.anchor.1,cursor.anchor.0) {
+                    cursor.pos.0 = 0;
+                } else {
+                    cursor.anchor.0 = 0;
+                }
+            }
+        });
+    }
+
+    // Delete, ctrl+backspace, ctrl+delete and ctrl+shift+k, at every cursor.
+    pub fn delete(&mut self, deletion : Deletion) {
+        if deletion != Deletion::Line {
+            let edits = self.cursors.iter().filter_map(|cursor| cursor.delete_text(&self.document, deletion)).collect();
+            self.edit(edits, EditKind::Deleting);
+            return;
+        }
+
+        // cursors on neighbouring lines would each reach for the same '\n', so delete runs of lines together.
+        let mut lines : Vec<(usize,usize)> = self.cursors.iter().map(|cursor| {
+            let lines = cursor.selected_lines();
+            (*lines.start(), *lines.end())
+        }).collect();
+        lines.sort();
+        let mut runs : Vec<(usize,usize)> = vec![];
+        for (first,last) in lines {
+            match runs.last_mut() {
+                Some(run) if first <= run.1+1 => run.1 = run.1.max(last),
+                _ => runs.push( (first,last) )
+            }
+        }
+        let edits = runs.into_iter().map(|(first,last)| (self.document.whole_lines(first..=last), String::new())).collect();
+        self.edit(edits, EditKind::Other);
+    }
+
+    // Shift+tab. Take one level of indentation off the start of every line with a cursor on it.
+    pub fn outdent(&mut self) {
+        let indent = self.document.indent;
+        let mut edits = vec![];
+        for cursor in &self.cursors {
+            for line in cursor.selected_lines() {
+                let text = self.document.line(line);
+                let width = if text.starts_with('\t') {
+                    1
+                } else {
+                    text.bytes().take(indent.width).take_while(|byte| *byte == b' ').count()
+                };
+                let start = self.document.pos_to_byte((0,line));
+                edits.push( (start..start+width, String::new()) );
+            }
+        }
+        self.edit(edits, EditKind::Other);
+    }
+
+    // Cursors that end up on top of each other, or with overlapping selections, become one.
+    //  The one added later wins, so the newest cursor stays the last.
+    fn merge_cursors(&mut self) {
+        let mut i = 0;
+        while i < self.cursors.len() {
+            let a = self.cursors[i].selection_range(&self.document);
+            let other = (i+1..self.cursors.len()).find(|j| {
+                let b = self.cursors[*j].selection_range(&self.document);
+                let (start,end) = (a.start.max(b.start), a.end.min(b.end));
+                start < end || (start == end && (a.is_empty() || b.is_empty()))
+            });
+
+            if let Some(j) = other {
+                let b = self.cursors[j].selection_range(&self.document);
+                let start = self.document.byte_to_pos(a.start.min(b.start));
+                let end = self.document.byte_to_pos(a.end.max(b.end));
+                let backwards = self.cursors[j].selection().0 == self.cursors[j].pos && self.cursors[j].has_selection();
+                self.cursors[j] = if backwards { Cursor::selecting(end, start) } else { Cursor::selecting(start, end) };
+                self.cursors.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    // Add a cursor, like with alt+click.
+    pub fn add_cursor(&mut self, pos : (usize,usize)) {
+        self.cursors.push(Cursor::new(pos));
+        self.merge_cursors();
+        self.history.seal();
+    }
+
+    // Add a cursor on the line above the top cursor, or below the bottom one.
+    pub fn add_cursor_vertical(&mut self, up : bool) {
+        let edge = if up {
+            self.cursors.iter().min_by_key(|cursor| (cursor.pos.1, cursor.pos.0))
+        } else {
+            self.cursors.iter().max_by_key(|cursor| (cursor.pos.1, cursor.pos.0))
+        };
+        let pos = edge.unwrap().pos;
+
+        let line = if up {
+            match pos.1.checked_sub(1) { Some(line) => line, None => return }
+        } else if pos.1 + 1 < self.document.len_lines() {
+            pos.1 + 1
+        } else {
+            return;
+        };
+        let tab_width = self.document.indent.width;
+        let column = cursor::visual_column(&self.document.line(pos.1), pos.0, tab_width);
+        let index = cursor::visual_column_to_index(&self.document.line(line), column, tab_width);
+        self.add_cursor((index,line));
+        self.scroll_to_cursor();
+    }
+
+    // Ctrl+D. Select the word under the newest cursor, or if it already has a selection,
+    //  add a cursor selecting the next place that text shows up.
+    pub fn add_next_occurrence(&mut self) {
+        let newest = *self.cursors.last().unwrap();
+        if !newest.has_selection() {
+            let word = cursor::word_at(&self.document.line(newest.pos.1), newest.pos.0);
+            if !word.is_empty() {
+                *self.cursors.last_mut().unwrap() = Cursor::selecting((word.start, newest.pos.1), (word.end, newest.pos.1));
+            }
+            return;
+        }
+
+        let range = newest.selection_range(&self.document);
+        let needle = self.document.slice(range.clone());
+        let mut from = range.end;
+        // skip over places that already have a cursor, until we get back to where we started.
+        while let Some(start) = self.document.find(&needle, from) {
+            let found = start..start + needle.len();
+            if found == range {
+                return;
+            }
+            if !self.cursors.iter().any(|cursor| cursor.selection_range(&self.document) == found) {
+                let (start,end) = (self.document.byte_to_pos(found.start), self.document.byte_to_pos(found.end));
+                self.cursors.push(Cursor::selecting(start, end));
+                self.history.seal();
+                self.scroll_to_cursor();
+                return;
+            }
+            from = found.end;
+        }
+    }
+
+    // Escape. Back to just the newest cursor, and no selection if that's all there was.
+    pub fn collapse_cursors(&mut self) {
+        if self.cursors.len() > 1 {
+            let newest = self.cursors.pop().unwrap();
+            self.cursors = vec![newest];
+        } else {
+            let cursor = &mut self.cursors[0];
+            cursor.anchor = cursor.pos;
+        }
+        self.history.seal();
+    }
+
+    // Is there more than a plain cursor to get rid of with collapse_cursors?
+    pub fn can_collapse(&self) -> bool {
+        self.cursors.len() > 1 || self.cursors[0].has_selection()
+    }
+
+    // The word or line around a position, as a selection.
+    fn select_unit(&self, pos : (usize,usize), unit : SelectUnit) -> Cursor {
+        match unit {
+            SelectUnit::Character => Cursor::new(pos),
+            SelectUnit::Word => {
+                let word = cursor::word_at(&self.document.line(pos.1), pos.0);
+                Cursor::selecting((word.start, pos.1), (word.end, pos.1))
+            }
+            SelectUnit::Line => {
+                // take the newline too, unless it is the last line.
+                let end = if pos.1 + 1 < self.document.len_lines() { (0, pos.1 + 1) } else { (self.document.line_len(pos.1), pos.1) };
+                Cursor::selecting((0, pos.1), end)
+            }
+        }
+    }
+
+    // The mouse went down on the text. Back to one cursor, selecting whatever the click selects.
+    pub fn click(&mut self, pos : (usize,usize), unit : SelectUnit) {
+        let cursor = self.select_unit(pos, unit);
+        self.cursors = vec![cursor];
+        self.drag = Some((cursor,unit));
+        self.history.seal();
+    }
+
+    // The mouse moved while held down. Select from what was clicked to here, a whole word
+    //  or line at a time if that is what was clicked.
+    pub fn drag(&mut self, pos : (usize,usize)) {
+        let Some((origin,unit)) = self.drag else { return };
+        let (origin_start,origin_end) = origin.selection();
+        let (start,end) = self.select_unit(pos, unit).selection();
+
+        let cursor = if (start.1,start.0) < (origin_start.1,origin_start.0) {
+            Cursor::selecting(origin_end, start)
+        } else {
+            Cursor::selecting(origin_start, end)
+        };
+        *self.cursors.last_mut().unwrap() = cursor;
+    }
+
+    // The mouse button came back up.
+    pub fn end_drag(&mut self) {
+        if self.drag.take().is_some() {
+            if let Some(text) = self.selected_text() {
+                self.clipboard.set(ClipboardKind::Primary, text);
+            }
+        }
+    }
+
+    pub fn dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // The document position under a point, relative to the top left of the gutter.
+    pub fn hit_test(&self, x : f32, y : f32) -> (usize,usize) {
+        let line_height = self.layout.line_height();
+        let row = ((y + self.scroll_offset() as f32) / line_height).floor();
+        if row < 0.0 {
+            return (0,0);
+        }
+
+        let mut rows_left = row as usize;
+        for (i,line) in self.document.lines.iter().enumerate() {
+            if rows_left < line.glyphs.len() {
+                return (line.index_at(rows_left, x - self.gutter + self.scroll_x as f32), i);
+            }
+            rows_left -= line.glyphs.len();
+        }
+
+        // below the last line.
+        let last = self.document.len_lines() - 1;
+        (self.document.line_len(last), last)
+    }
+
+    // Type a whole string at every cursor at once, replacing any selections. Much faster
+    //  than a character at a time, since each line it touches is only laid out once.
+    pub fn insert_str(&mut self, text : &str) {
+        // the document only has '\n' between lines.
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        let edits = self.cursors.iter().map(|cursor| (cursor.selection_range(&self.document), text.clone())).collect();
+        self.edit(edits, EditKind::Other);
+    }
+
+    // The text of every selection, one per line, in the order they are in the document.
+    fn selected_text(&self) -> Option<String> {
+        let mut ranges : Vec<Range<usize>> = self.cursors.iter()
+            .filter(|cursor| cursor.has_selection())
+            .map(|cursor| cursor.selection_range(&self.document)).collect();
+        if ranges.is_empty() {
+            return None;
+        }
+        ranges.sort_by_key(|range| range.start);
+        Some( ranges.into_iter().map(|range| self.document.slice(range)).collect::<Vec<String>>().join("\n") )
+    }
+
+    pub fn copy(&mut self) {
+        if let Some(text) = self.selected_text() {
+            self.clipboard.set(ClipboardKind::Clipboard, text);
+        }
+    }
+
+    pub fn cut(&mut self) {
+        if let Some(text) = self.selected_text() {
+            self.clipboard.set(ClipboardKind::Clipboard, text);
+            let edits = self.cursors.iter().map(|cursor| (cursor.selection_range(&self.document), String::new())).collect();
+            self.edit(edits, EditKind::Other);
+        }
+    }
+
+    pub fn paste(&mut self, kind : ClipboardKind) {
+        let Some(text) = self.clipboard.get(kind) else { return };
+        let lines : Vec<&str> = text.lines().collect();
+
+        // copied from as many cursors as there are now, so give each cursor its own line back.
+        if self.cursors.len() > 1 && lines.len() == self.cursors.len() {
+            let mut order : Vec<usize> = (0..self.cursors.len()).collect();
+            order.sort_by_key(|i| (self.cursors[*i].pos.1, self.cursors[*i].pos.0));
+            let edits = order.into_iter().zip(lines)
+                .map(|(i,line)| (self.cursors[i].selection_range(&self.document), line.to_string())).collect();
+            self.edit(edits, EditKind::Other);
+        } else {
+            self.insert_str(&text);
+        }
+    }
+
+    // Straight there, in rows. Stops any smooth scrolling.
+    pub fn scroll_by(&mut self, lines : f64) {
+        self.scroll_by_pixels(lines * self.layout.line_height() as f64);
+    }
+
+    // Straight there, like a trackpad that already moves smoothly by itself.
+    pub fn scroll_by_pixels(&mut self, pixels : f64) {
+        self.scroll += pixels;
+        self.clamp_scroll();
+        self.scroll_target = self.scroll;
+    }
+
+    // Some notches of the mouse wheel, wheel_lines rows each.
+    pub fn wheel(&mut self, notches : f64) {
+        if !self.smooth_scroll {
+            self.scroll_by(notches * self.wheel_lines);
+            return;
+        }
+        self.scroll_target += notches * self.wheel_lines * self.layout.line_height() as f64;
+        self.scroll_target = self.scroll_target.clamp(0.0, self.max_scroll());
+    }
+
+    // Move the smooth scroll along. Returns if it is still moving, so the frontend knows to keep drawing.
+    pub fn animate(&mut self, seconds : f64) -> bool {
+        if self.scroll == self.scroll_target {
+            return false;
+        }
+        // close most of the distance quickly, then ease in.
+        self.scroll += (self.scroll_target - self.scroll) * (1.0 - (-seconds * 15.0).exp());
+        if (self.scroll_target - self.scroll).abs() < 0.5 {
+            self.scroll = self.scroll_target;
+        }
+        true
+    }
+
+    // So far down that the last row is at the bottom of the view.
+    fn max_scroll(&self) -> f64 {
+        (self.total_rows() as f64 * self.layout.line_height() as f64 - self.size.1 as f64).max(0.0)
+    }
+
+    // Don't scroll above the first row, or so far down that the last row leaves the bottom.
+    fn clamp_scroll(&mut self) {
+        self.scroll = self.scroll.clamp(0.0, self.max_scroll());
+        self.scroll_target = self.scroll_target.clamp(0.0, self.max_scroll());
+    }
+
+    // The row the newest cursor is on, counting from the top of the document.
+    fn cursor_row(&self) -> i64 {
+        let cursor = self.cursors.last().unwrap();
+        self.rows_before(cursor.pos.1) + self.document.lines[cursor.pos.1].row_of(cursor.pos.0) as i64
+    }
+
+    // Ctrl+L. Scroll so the newest cursor is in the middle of the view.
+    pub fn center_cursor(&mut self) {
+        let line_height = self.layout.line_height() as f64;
+        self.scroll = (self.cursor_row() as f64 + 0.5) * line_height - self.size.1 as f64 / 2.0;
+        self.clamp_scroll();
+        self.scroll_target = self.scroll;
+    }
+
+    // Sideways, in pixels. Wrapped text always fits, so there is nothing to scroll.
+    //  Stops once the end of the longest line is in view, with room for the cursor after it.
+    pub fn scroll_x_by(&mut self, pixels : f64) {
+        if self.layout.wrap == Wrap::None {
+            let widest = self.document.lines.iter().map(|line| line.width()).fold(0.0, f32::max) as f64;
+            let max = (widest - self.text_size().0 as f64 + self.layout.line_height() as f64).max(0.0);
+            self.scroll_x = (self.scroll_x + pixels).clamp(0.0, max);
+        }
+    }
+
+    // Where to draw the ruler, relative to the left of the gutter. Only when wrapping at a column.
+    pub fn ruler_x(&self) -> Option<i64> {
+        match self.layout.wrap {
+            Wrap::Column(columns) if self.ruler => Some((self.gutter + self.layout.column_x(columns)) as i64 - self.scroll_x as i64),
+            _ => None
+        }
+    }
+
+    // Scroll just enough that the newest cursor is in view, with scroll_margin rows around it when there's room.
+    pub fn scroll_to_cursor(&mut self) {
+        let line_height = self.layout.line_height() as f64;
+        let margin = self.scroll_margin.min(self.page_rows().saturating_sub(1) / 2) as f64;
+        let top = (self.cursor_row() as f64 - margin) * line_height;
+        let bottom = (self.cursor_row() as f64 + 1.0 + margin) * line_height;
+        if top < self.scroll {
+            self.scroll = top;
+        } else if bottom > self.scroll + self.size.1 as f64 {
+            self.scroll = bottom - self.size.1 as f64;
+        }
+        self.clamp_scroll();
+        self.scroll_target = self.scroll;
+
+        if self.layout.wrap == Wrap::None {
+            let cursor = self.cursors.last().unwrap();
+            let line = &self.document.lines[cursor.pos.1];
+            let x = line.x_at(line.row_of(cursor.pos.0), cursor.pos.0) as f64;
+            let width = self.text_size().0 as f64 - self.layout.line_height() as f64; // leave room for the cursor itself
+            if x < self.scroll_x {
+                self.scroll_x = x;
+            } else if x > self.scroll_x + width {
+                self.scroll_x = x - width;
+            }
+        }
+    }
+
+    // The scroll, to the nearest pixel.
+    pub fn scroll_offset(&self) -> i64 {
+        self.scroll.round() as i64
+    }
+
+    // How many rows the whole document wraps to.
+    pub fn total_rows(&self) -> i64 {
+        self.rows_before(self.document.len_lines())
+    }
+
+    // How many rows are drawn above a line.
+    pub fn rows_before(&self, line : usize) -> i64 {
+        self.document.lines[..line].iter().map(|line| line.glyphs.len() as i64).sum()
+    }
+
+    // Where to draw a cursor, relative to the top left of the gutter. (x, y, width, height)
+    pub fn cursor_rect(&self, cursor : &Cursor) -> (i64,i64,u32,u32) {
+        let line_height = self.layout.line_height();
+        // get number of rows proceeding.
+        let y_acc = self.rows_before(cursor.pos.1);
+
+        let (x,y,w) = cursor.calc_cursor_pos(&self.document.lines[cursor.pos.1], line_height)
+            .expect("You are bad at programming.");
+        (x + self.gutter as i64 - self.scroll_x as i64, y + y_acc * line_height as i64 - self.scroll_offset(), w, line_height as u32)
+    }
+
+    // The highlight behind every selection, one rectangle per row it covers. Relative to the
+    //  top left of the gutter, like cursor_rect.
+    pub fn selection_rects(&self) -> Vec<(i64,i64,u32,u32)> {
+        let line_height = self.layout.line_height();
+        let mut rects = vec![];
+        for cursor in self.cursors.iter().filter(|cursor| cursor.has_selection()) {
+            let (start,end) = cursor.selection();
+            let mut y_acc = self.rows_before(start.1);
+
+            for line_index in start.1..=end.1 {
+                let line = &self.document.lines[line_index];
+                let from = if line_index == start.1 { start.0 } else { 0 };
+                let to = if line_index == end.1 { end.0 } else { line.text_len() };
+
+                for row in 0..line.glyphs.len() {
+                    // the part of the selection on this row.
+                    let a = from.max(line.breaks[row]);
+                    let b = to.min(line.breaks[row+1]);
+                    // the selection goes past the end of this line, so show the newline as selected too.
+                    let newline = row + 1 == line.glyphs.len() && line_index != end.1;
+
+                    if a < b || newline {
+                        let x = line.x_at(row, a);
+                        let mut x_end = line.x_at(row, b);
+                        if newline {
+                            x_end += line_height / 2.0;
+                        }
+                        let y = y_acc * line_height as i64 - self.scroll_offset();
+                        rects.push( ((x + self.gutter - self.scroll_x as f32).round() as i64, y, (x_end - x).round() as u32, line_height as u32) );
+                    }
+                    y_acc += 1;
+                }
+            }
+        }
+        rects
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::layout::Monospace;
+
+    fn editor(text : &str) -> Editor {
+        let metrics = Box::new(Monospace { advance : 10.0, line_height : 20.0 });
+        let mut editor = Editor::new(String::from("/nonexistent/manip-test"), metrics, (800,600));
+        editor.set_document(Document::from_bytes(String::from("test"), text.as_bytes()), false);
+        editor
+    }
+
+    fn text(editor : &Editor) -> String {
+        editor.document.slice(0..editor.document.len_bytes())
+    }
+
+    fn positions(editor : &Editor) -> Vec<(usize,usize)> {
+        editor.cursors.iter().map(|cursor| cursor.pos).collect()
+    }
+
+    #[test]
+    fn typing_joins_graphemes() {
+        // the 'e' and the accent become one grapheme, and the cursor goes after both.
+        let mut editor = editor("\u{301}abc");
+        editor.insert_cursor('e');
+        assert_eq!(text(&editor), "e\u{301}abc");
+        assert_eq!(positions(&editor), vec![(3,0)]);
+        editor.cursor_rect(&editor.cursors[0]);
+    }
+
+    #[test]
+    fn cursor_inside_a_grapheme_draws_on_it() {
+        let editor = editor("e\u{301}x");
+        let cursor = Cursor::new((1,0));
+        assert_eq!(editor.cursor_rect(&cursor), editor.cursor_rect(&Cursor::new((0,0))));
+    }
+
+    #[test]
+    fn moves_over_whole_graphemes() {
+        let mut editor = editor("a\u{1F469}\u{200D}\u{1F4BB}e\u{301}");
+        editor.move_cursor(CursorMovement::Right, false);
+        editor.move_cursor(CursorMovement::Right, false);
+        assert_eq!(positions(&editor), vec![(12,0)]);
+        editor.move_cursor(CursorMovement::Right, false);
+        assert_eq!(positions(&editor), vec![(15,0)]);
+        editor.insert_cursor('\u{8}');
+        assert_eq!(text(&editor), "a\u{1F469}\u{200D}\u{1F4BB}");
+        editor.insert_cursor('\u{8}');
+        assert_eq!(text(&editor), "a");
+    }
+
+    #[test]
+    fn map_offset_around_edits() {
+        let edits = vec![(2..4, String::from("xyz")), (6..6, String::from("ab"))];
+        assert_eq!(map_offset(1, &edits), 1);
+        assert_eq!(map_offset(2, &edits), 5); // replaced, ends up after what replaced it
+        assert_eq!(map_offset(3, &edits), 5);
+        assert_eq!(map_offset(4, &edits), 5);
+        assert_eq!(map_offset(5, &edits), 6);
+        assert_eq!(map_offset(6, &edits), 9); // right where text went in
+        assert_eq!(map_offset(8, &edits), 11);
+    }
+
+    #[test]
+    fn edit_moves_every_cursor() {
+        let mut editor = editor("ab\ncd");
+        editor.cursors = vec![Cursor::new((1,0)), Cursor::new((1,1))];
+        editor.edit(vec![(4..4, String::from("x")), (1..1, String::from("x"))], EditKind::Typing);
+        assert_eq!(text(&editor), "axb\ncxd");
+        assert_eq!(positions(&editor), vec![(2,0), (2,1)]);
+        assert!(editor.modified());
+
+        editor.undo();
+        assert_eq!(text(&editor), "ab\ncd");
+        assert_eq!(positions(&editor), vec![(1,0), (1,1)]);
+        assert!(!editor.modified());
+        editor.redo();
+        assert_eq!(text(&editor), "axb\ncxd");
+    }
+
+    #[test]
+    fn edit_joins_lines_and_merges_cursors() {
+        let mut editor = editor("ab\ncd");
+        editor.cursors = vec![Cursor::new((2,0)), Cursor::new((0,1))];
+        // both cursors ask to delete the same newline.
+        editor.edit(vec![(2..3, String::new()), (2..3, String::new())], EditKind::Deleting);
+        assert_eq!(text(&editor), "abcd");
+        assert_eq!(positions(&editor), vec![(2,0)]);
+    }
+
+    #[test]
+    fn edit_skips_overlapping_edits() {
+        let mut editor = editor("abcdef");
+        editor.edit(vec![(1..4, String::from("x")), (2..5, String::from("y"))], EditKind::Other);
+        assert_eq!(text(&editor), "axef");
+    }
+
+    #[test]
+    fn next_occurrence() {
+        let mut editor = editor("foo bar foo\nfoo");
+        editor.cursors = vec![Cursor::new((1,0))];
+        // the first press only selects the word.
+        editor.add_next_occurrence();
+        assert_eq!(editor.cursors, vec![Cursor::selecting((0,0), (3,0))]);
+        for _ in 0..4 {
+            editor.add_next_occurrence();
+        }
+        assert_eq!(editor.cursors.iter().map(|cursor| cursor.selection()).collect::<Vec<_>>(), vec![((0,0),(3,0)), ((8,0),(11,0)), ((0,1),(3,1))]);
+
+        editor.insert_str("x");
+        assert_eq!(text(&editor), "x bar x\nx");
+        assert_eq!(positions(&editor), vec![(1,0), (7,0), (1,1)]);
+        editor.undo();
+        assert_eq!(text(&editor), "foo bar foo\nfoo");
+    }
+
+    #[test]
+    fn cursors_above_and_below() {
+        let mut editor = editor("abc\na\nabc");
+        editor.cursors = vec![Cursor::new((2,0))];
+        editor.add_cursor_vertical(false);
+        editor.add_cursor_vertical(false);
+        editor.add_cursor_vertical(false);
+        // each one goes below the last, at the column it's at.
+        assert_eq!(positions(&editor), vec![(2,0), (1,1), (1,2)]);
+        // typing at all of them at once is one undo step.
+        editor.insert_cursor('x');
+        editor.insert_cursor('y');
+        assert_eq!(text(&editor), "abxyc\naxy\naxybc");
+        editor.undo();
+        assert_eq!(text(&editor), "abc\na\nabc");
+
+        // a cursor added where there already is one merges with it.
+        editor.add_cursor((1,1));
+        assert_eq!(editor.cursors.len(), 3);
+        editor.collapse_cursors();
+        assert_eq!(editor.cursors.len(), 1);
+    }
+
+    #[test]
+    fn tab_goes_to_the_next_stop() {
+        let mut editor = editor("ab");
+        editor.cursors = vec![Cursor::new((1,0))];
+        editor.set_indent(Indent { soft : true, width : 4 });
+        editor.insert_cursor('\t');
+        assert_eq!(text(&editor), "a   b");
+        editor.set_indent(Indent { soft : false, width : 4 });
+        editor.insert_cursor('\t');
+        assert_eq!(text(&editor), "a   \tb");
+    }
+
+    #[test]
+    fn tab_indents_selected_lines() {
+        let mut editor = editor("ab\ncd\nef");
+        editor.set_indent(Indent { soft : true, width : 2 });
+        editor.cursors = vec![Cursor::selecting((0,0), (1,1))];
+        editor.insert_cursor('\t');
+        assert_eq!(text(&editor), "  ab\n  cd\nef");
+        // the new indentation is selected too.
+        assert_eq!(editor.cursors, vec![Cursor::selecting((0,0), (3,1))]);
+
+        // the same backwards, and a redo puts the selection back the same way.
+        let mut editor = self::editor("ab\ncd");
+        editor.set_indent(Indent { soft : false, width : 4 });
+        editor.cursors = vec![Cursor::selecting((1,1), (0,0))];
+        editor.insert_cursor('\t');
+        assert_eq!(text(&editor), "\tab\n\tcd");
+        assert_eq!(editor.cursors, vec![Cursor::selecting((2,1), (0,0))]);
+        editor.undo();
+        editor.redo();
+        assert_eq!(editor.cursors, vec![Cursor::selecting((2,1), (0,0))]);
+
+        editor.outdent();
+        assert_eq!(text(&editor), "ab\ncd");
+    }
+
+    #[test]
+    fn outdent_takes_one_level() {
+        let mut editor = editor("      a\n\tb\n c");
+        editor.set_indent(Indent { soft : true, width : 4 });
+        editor.cursors = vec![Cursor::selecting((0,0), (1,2))];
+        editor.outdent();
+        assert_eq!(text(&editor), "  a\nb\nc");
+    }
+
+    #[test]
+    fn forward_delete() {
+        let mut editor = editor("ae\u{301}\nb");
+        editor.cursors = vec![Cursor::new((1,0))];
+        editor.delete(Deletion::Forward);
+        assert_eq!(text(&editor), "a\nb");
+        // at the end of a line, the next one comes up.
+        editor.delete(Deletion::Forward);
+        assert_eq!(text(&editor), "ab");
+        editor.move_cursor(CursorMovement::DocumentEnd, false);
+        editor.delete(Deletion::Forward);
+        assert_eq!(text(&editor), "ab");
+    }
+
+    #[test]
+    fn word_deletion() {
+        let mut editor = editor("one two  three\nfour");
+        editor.cursors = vec![Cursor::new((9,0))];
+        editor.delete(Deletion::WordLeft);
+        assert_eq!(text(&editor), "one three\nfour");
+        assert_eq!(positions(&editor), vec![(4,0)]);
+        editor.delete(Deletion::WordRight);
+        assert_eq!(text(&editor), "one \nfour");
+        editor.delete(Deletion::WordRight);
+        assert_eq!(text(&editor), "one four");
+
+        // a selection is deleted as it is.
+        editor.cursors = vec![Cursor::selecting((1,0), (5,0))];
+        editor.delete(Deletion::WordLeft);
+        assert_eq!(text(&editor), "oour");
+    }
+
+    #[test]
+    fn delete_lines() {
+        let mut editor = editor("a\nb\nc\nd");
+        // cursors on neighbouring lines take them out together, and the last line takes the newline before it.
+        editor.cursors = vec![Cursor::new((0,1)), Cursor::new((1,2))];
+        editor.delete(Deletion::Line);
+        assert_eq!(text(&editor), "a\nd");
+        editor.move_cursor(CursorMovement::DocumentEnd, false);
+        editor.delete(Deletion::Line);
+        assert_eq!(text(&editor), "a");
+        editor.undo();
+        editor.undo();
+        assert_eq!(text(&editor), "a\nb\nc\nd");
+    }
+
+    #[test]
+    fn word_and_line_navigation() {
+        let mut editor = editor("    let x = 1;\nnext");
+        editor.cursors = vec![Cursor::new((6,0))];
+        editor.move_cursor(CursorMovement::WordRight, false);
+        assert_eq!(positions(&editor), vec![(7,0)]);
+        editor.move_cursor(CursorMovement::WordRight, false);
+        assert_eq!(positions(&editor), vec![(9,0)]);
+        editor.move_cursor(CursorMovement::WordLeft, false);
+        editor.move_cursor(CursorMovement::WordLeft, false);
+        assert_eq!(positions(&editor), vec![(4,0)]);
+
+        // home goes to the indentation first, then the start of the line, and back.
+        editor.move_cursor(CursorMovement::End, false);
+        assert_eq!(positions(&editor), vec![(14,0)]);
+        editor.move_cursor(CursorMovement::Home, false);
+        assert_eq!(positions(&editor), vec![(4,0)]);
+        editor.move_cursor(CursorMovement::Home, false);
+        assert_eq!(positions(&editor), vec![(0,0)]);
+        editor.move_cursor(CursorMovement::Home, false);
+        assert_eq!(positions(&editor), vec![(4,0)]);
+
+        // word movement crosses lines, and shift selects.
+        editor.move_cursor(CursorMovement::End, false);
+        editor.move_cursor(CursorMovement::WordRight, true);
+        assert_eq!(editor.cursors, vec![Cursor::selecting((14,0), (0,1))]);
+        editor.move_cursor(CursorMovement::DocumentStart, true);
+        assert_eq!(editor.cursors, vec![Cursor::selecting((14,0), (0,0))]);
+        editor.move_cursor(CursorMovement::DocumentEnd, false);
+        assert_eq!(editor.cursors, vec![Cursor::new((4,1))]);
+    }
+
+    #[test]
+    fn page_movement() {
+        let text = vec!["line"; 100].join("\n");
+        let mut editor = editor(&text);
+        // 600 pixels of 20 pixel rows.
+        assert_eq!(editor.page_rows(), 30);
+        editor.cursors = vec![Cursor::new((2,0))];
+        editor.move_cursor(CursorMovement::PageDown(editor.page_rows()), false);
+        assert_eq!(positions(&editor), vec![(2,30)]);
+        editor.move_cursor(CursorMovement::PageUp(50), false);
+        assert_eq!(positions(&editor), vec![(0,0)]);
+        editor.move_cursor(CursorMovement::PageDown(200), false);
+        assert_eq!(positions(&editor), vec![(4,99)]);
+    }
+
+    #[test]
+    fn goal_column_sticks() {
+        let mut editor = editor("abcdef\nab\n\nabcdef");
+        editor.cursors = vec![Cursor::new((5,0))];
+        editor.move_cursor(CursorMovement::Down, false);
+        assert_eq!(positions(&editor), vec![(2,1)]);
+        editor.move_cursor(CursorMovement::Down, false);
+        assert_eq!(positions(&editor), vec![(0,2)]);
+        editor.move_cursor(CursorMovement::Down, false);
+        assert_eq!(positions(&editor), vec![(5,3)]);
+
+        // moving sideways, or typing, starts from wherever the cursor is now.
+        editor.move_cursor(CursorMovement::Up, false);
+        editor.move_cursor(CursorMovement::Up, false);
+        editor.move_cursor(CursorMovement::Left, false);
+        editor.move_cursor(CursorMovement::Down, false);
+        editor.move_cursor(CursorMovement::Down, false);
+        assert_eq!(positions(&editor), vec![(1,3)]);
+        editor.move_cursor(CursorMovement::Up, false);
+        editor.move_cursor(CursorMovement::Up, false);
+        editor.insert_cursor('x');
+        editor.move_cursor(CursorMovement::Up, false);
+        assert_eq!(positions(&editor), vec![(2,0)]);
+    }
+
+    // Ten characters to a row, with no gutter in the way.
+    fn narrow(text : &str) -> Editor {
+        let mut editor = editor(text);
+        editor.set_line_numbers(LineNumbers::Off);
+        editor.resize((108,600));
+        editor
+    }
+
+    #[test]
+    fn up_and_down_by_rows() {
+        let mut editor = narrow("aaaa bbbb cccc dddd\nx");
+        assert_eq!(editor.total_rows(), 3);
+        editor.cursors = vec![Cursor::new((1,0))];
+        editor.move_cursor(CursorMovement::Down, false);
+        assert_eq!(positions(&editor), vec![(11,0)]);
+        editor.move_cursor(CursorMovement::Down, false);
+        assert_eq!(positions(&editor), vec![(1,1)]);
+        editor.move_cursor(CursorMovement::Up, false);
+        editor.move_cursor(CursorMovement::Up, false);
+        assert_eq!(positions(&editor), vec![(1,0)]);
+
+        // end stops at the end of the row before going to the end of the line.
+        editor.move_cursor(CursorMovement::End, false);
+        assert_eq!(positions(&editor), vec![(9,0)]);
+        editor.move_cursor(CursorMovement::End, false);
+        assert_eq!(positions(&editor), vec![(19,0)]);
+        editor.move_cursor(CursorMovement::Home, false);
+        assert_eq!(positions(&editor), vec![(10,0)]);
+    }
+
+    #[test]
+    fn up_and_down_by_lines() {
+        let mut editor = narrow("aaaa bbbb cccc dddd\nxyz");
+        editor.logical_lines = true;
+        editor.cursors = vec![Cursor::new((1,0))];
+        editor.move_cursor(CursorMovement::Down, false);
+        assert_eq!(positions(&editor), vec![(1,1)]);
+        // up lands on the last row of a wrapped line.
+        editor.move_cursor(CursorMovement::Up, false);
+        assert_eq!(positions(&editor), vec![(11,0)]);
+    }
+
+    #[test]
+    fn format_changes_until_changed_back() {
+        let mut editor = editor("a\r\nb\r\n");
+        assert!(!editor.modified());
+        for line_ending in [LineEnding::Cr, LineEnding::Lf, LineEnding::CrLf] {
+            editor.set_line_ending(line_ending);
+            assert_eq!(editor.modified(), line_ending != LineEnding::CrLf);
+        }
+        editor.set_final_newline(false);
+        assert!(editor.modified());
+        editor.set_final_newline(true);
+        assert!(!editor.modified());
+        editor.set_encoding(Encoding::Utf16Le);
+        assert!(editor.modified());
+        editor.set_encoding(Encoding::Utf8);
+        assert!(!editor.modified());
+    }
+
+    #[test]
+    fn mixed_line_endings_stay_modified() {
+        let mut editor = editor("a\r\nb\nc\r\n");
+        assert!(editor.modified());
+        editor.set_line_ending(LineEnding::Lf);
+        editor.set_line_ending(LineEnding::CrLf);
+        assert!(editor.modified());
+    }
+
+    #[test]
+    fn read_only_never_changes() {
+        let mut editor = editor("a\0b");
+        editor.edit(vec![(0..0, String::from("x"))], EditKind::Typing);
+        assert_eq!(text(&editor), "a\0b");
+        assert!(!editor.modified());
+    }
+}
@@ -0,0 +1,364 @@
+// Editing a file as bytes instead of text, for files that aren't text or can't be read as any.
+//  Each row is an offset in the gutter, the bytes in hex, then the same bytes as ascii.
+use std::ops::Range;
+use super::cursor::CursorMovement;
+use super::document::{self, SaveError};
+use super::layout::Metrics;
+
+pub const ROW_BYTES : usize = 16;
+const OFFSET_DIGITS : usize = 8;
+const ASCII_COLUMN : usize = 3 * ROW_BYTES + 3; // in characters from the left of the hex, after the gap between the two
+
+pub struct HexEditor {
+    pub file_name : String,
+    bytes : Vec<u8>,
+    pub cursor : usize, // the byte the cursor is on. At bytes.len() it's past the end, where typing adds more.
+    low : bool, // the first digit of the cursor's byte has been typed, the next one goes in the second
+    pub insert : bool, // typing adds bytes instead of replacing them
+    pub ascii : bool, // typing goes in the ascii column instead of the hex
+    modified : bool,
+    metrics : Box<dyn Metrics>, // only for its character width and row height, it needs a monospace font
+    pub scroll : f64, // pixels, like the editor's
+    size : (f32,f32)
+}
+
+impl HexEditor {
+    // modified is whether the bytes differ from what's saved, like when they came from unsaved text.
+    pub fn new(file_name : String, bytes : Vec<u8>, modified : bool, metrics : Box<dyn Metrics>, screen_size : (u32,u32)) -> Self {
+        HexEditor { file_name, bytes, cursor : 0, low : false, insert : false, ascii : false, modified, metrics, scroll : 0.0, size : (screen_size.0 as f32, screen_size.1 as f32) }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn modified(&self) -> bool {
+        self.modified
+    }
+
+    // Straight to disk, the same way the editor saves text.
+    pub fn save_file(&mut self) -> Result<(), SaveError> {
+        document::save(&self.file_name, |file| file.write_all(&self.bytes))?;
+        self.modified = false;
+        Ok(())
+    }
+
+    // screen_size is the part of the window the gutter and bytes are drawn in.
+    pub fn resize(&mut self, screen_size : (u32,u32)) {
+        self.size = (screen_size.0 as f32, screen_size.1 as f32);
+        self.clamp_scroll();
+    }
+
+    fn cell(&self) -> f32 {
+        self.metrics.advance("0")
+    }
+
+    fn line_height(&self) -> f32 {
+        self.metrics.line_height()
+    }
+
+    // Where the bytes start, right of the offsets. Like the editor, everything in pixels is measured from the gutter's left.
+    pub fn text_x(&self) -> f32 {
+        self.cell() * (OFFSET_DIGITS + 2) as f32
+    }
+
+    // Always one more than is full, so there's somewhere to add bytes at the end.
+    pub fn len_rows(&self) -> usize {
+        self.bytes.len() / ROW_BYTES + 1
+    }
+
+    // The rows at least partly on screen.
+    pub fn visible_rows(&self) -> Range<usize> {
+        let line_height = self.line_height() as f64;
+        let first = (self.scroll / line_height) as usize;
+        let last = ((self.scroll + self.size.1 as f64) / line_height).ceil() as usize;
+        first.min(self.len_rows())..last.min(self.len_rows())
+    }
+
+    // How far down the text area a row is drawn.
+    pub fn row_y(&self, row : usize) -> i64 {
+        (row as f32 * self.line_height()) as i64 - self.scroll_offset()
+    }
+
+    // The offsets on screen, and how far down to draw each one, for the gutter.
+    pub fn gutter_labels(&self) -> Vec<(String,i64)> {
+        self.visible_rows().map(|row| (format!("{:08X}", row * ROW_BYTES), self.row_y(row))).collect()
+    }
+
+    // The hex and the ascii of a row, and where to draw each, from the left of the gutter.
+    pub fn row_text(&self, row : usize) -> [(f32,String); 2] {
+        let start = (row * ROW_BYTES).min(self.bytes.len());
+        let bytes = &self.bytes[start..(start + ROW_BYTES).min(self.bytes.len())];
+        let mut hex = String::new();
+        for (i,byte) in bytes.iter().enumerate() {
+            if i == ROW_BYTES / 2 {
+                hex.push(' ');
+            }
+            hex += &format!("{:02X} ", byte);
+        }
+        // anything that isn't printable ascii is a dot, so the columns stay lined up.
+        let ascii = bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+        [(self.column_x(1), hex), (self.column_x(ASCII_COLUMN), ascii)]
+    }
+
+    // Characters from the left of the hex, to pixels from the left of the gutter.
+    fn column_x(&self, column : usize) -> f32 {
+        self.text_x() + self.cell() * column as f32
+    }
+
+    // Where a byte of a row starts, in characters from the left of the hex.
+    fn byte_column(index : usize, ascii : bool) -> usize {
+        if ascii {
+            ASCII_COLUMN + index
+        } else {
+            1 + 3 * index + index / (ROW_BYTES / 2)
+        }
+    }
+
+    // Where to draw the cursor, relative to the top left of the gutter. (x, y, width, height)
+    //  A block over the digit when typing replaces it, and a bar before it when typing inserts.
+    pub fn cursor_rect(&self) -> (i64,i64,u32,u32) {
+        let column = HexEditor::byte_column(self.cursor % ROW_BYTES, self.ascii) + self.low as usize;
+        let width = if self.insert { 2 } else { self.cell().round() as u32 };
+        (self.column_x(column).round() as i64, self.row_y(self.cursor / ROW_BYTES), width, self.line_height() as u32)
+    }
+
+    // The same byte in the other column, so it's easy to see which is which.
+    pub fn selection_rects(&self) -> Vec<(i64,i64,u32,u32)> {
+        if self.cursor >= self.bytes.len() {
+            return vec![];
+        }
+        let column = HexEditor::byte_column(self.cursor % ROW_BYTES, !self.ascii);
+        let width = if self.ascii { 2.0 } else { 1.0 } * self.cell();
+        vec![(self.column_x(column).round() as i64, self.row_y(self.cursor / ROW_BYTES), width.round() as u32, self.line_height() as u32)]
+    }
+
+    // Put the cursor on the byte under a point, relative to the top left of the gutter. Clicking
+    //  the ascii column types into it.
+    pub fn click(&mut self, x : f32, y : f32) {
+        let row = ((y as f64 + self.scroll) / self.line_height() as f64).max(0.0) as usize;
+        let column = ((x - self.text_x()) / self.cell()).max(0.0) as usize;
+        self.ascii = column + 1 >= ASCII_COLUMN;
+        let index = if self.ascii {
+            column.saturating_sub(ASCII_COLUMN)
+        } else {
+            // the gap in the middle counts as the byte before it.
+            column.saturating_sub(1 + (column > 3 * ROW_BYTES / 2) as usize) / 3
+        };
+        self.cursor = (row * ROW_BYTES + index.min(ROW_BYTES - 1)).min(self.bytes.len());
+        self.low = false;
+    }
+
+    // A byte at a time. Up and down go a row, home and end to either end of one.
+    pub fn move_cursor(&mut self, direction : CursorMovement) {
+        let row_start = self.cursor - self.cursor % ROW_BYTES;
+        let len = self.bytes.len();
+        self.cursor = match direction {
+            CursorMovement::Left | CursorMovement::WordLeft => self.cursor.saturating_sub(1),
+            CursorMovement::Right | CursorMovement::WordRight => (self.cursor + 1).min(len),
+            CursorMovement::Up | CursorMovement::LineUp => self.cursor.checked_sub(ROW_BYTES).unwrap_or(self.cursor),
+            CursorMovement::Down | CursorMovement::LineDown => if self.cursor + ROW_BYTES <= len { self.cursor + ROW_BYTES } else { self.cursor },
+            CursorMovement::Home => row_start,
+            CursorMovement::End => (row_start + ROW_BYTES - 1).min(len),
+            CursorMovement::PageUp(rows) => self.cursor.saturating_sub(rows * ROW_BYTES),
+            CursorMovement::PageDown(rows) => (self.cursor + rows * ROW_BYTES).min(len),
+            CursorMovement::DocumentStart => 0,
+            CursorMovement::DocumentEnd => len
+        };
+        self.low = false;
+        self.scroll_to_cursor();
+    }
+
+    // Tab goes between the hex and the ascii.
+    pub fn switch_column(&mut self) {
+        self.ascii = !self.ascii;
+        self.low = false;
+    }
+
+    // Hex digits in the hex column, or any ascii in the ascii column. Backspace takes the byte before
+    //  the cursor away when inserting, and only moves back when replacing, so nothing shifts over.
+    pub fn type_char(&mut self, character : char) {
+        match character {
+            '\u{8}' => {
+                if self.insert && self.cursor > 0 {
+                    self.bytes.remove(self.cursor - 1);
+                    self.modified = true;
+                }
+                self.cursor = self.cursor.saturating_sub(1);
+                self.low = false;
+            }
+            '\t' => self.switch_column(),
+            _ if self.ascii => {
+                if character.is_ascii() && !character.is_ascii_control() {
+                    self.put(character as u8);
+                    self.cursor += 1;
+                }
+            }
+            _ => {
+                let Some(digit) = character.to_digit(16) else { return };
+                let digit = digit as u8;
+                if self.low {
+                    self.bytes[self.cursor] = self.bytes[self.cursor] & 0xF0 | digit;
+                    self.cursor += 1;
+                } else {
+                    let low = self.bytes.get(self.cursor).filter(|_| !self.insert).map_or(0, |byte| byte & 0x0F);
+                    self.put(digit << 4 | low);
+                }
+                self.modified = true;
+                self.low = !self.low;
+            }
+        }
+        self.scroll_to_cursor();
+    }
+
+    // Puts a byte at the cursor, either over the one there or in front of it.
+    fn put(&mut self, byte : u8) {
+        if self.insert || self.cursor == self.bytes.len() {
+            self.bytes.insert(self.cursor, byte);
+        } else {
+            self.bytes[self.cursor] = byte;
+        }
+        self.modified = true;
+    }
+
+    // The byte under the cursor, in either mode.
+    pub fn delete(&mut self) {
+        if self.cursor < self.bytes.len() {
+            self.bytes.remove(self.cursor);
+            self.modified = true;
+        }
+        self.low = false;
+    }
+
+    // Straight there, in rows.
+    pub fn scroll_by(&mut self, lines : f64) {
+        self.scroll_by_pixels(lines * self.line_height() as f64);
+    }
+
+    pub fn scroll_by_pixels(&mut self, pixels : f64) {
+        self.scroll += pixels;
+        self.clamp_scroll();
+    }
+
+    fn clamp_scroll(&mut self) {
+        let max = (self.len_rows() as f64 * self.line_height() as f64 - self.size.1 as f64).max(0.0);
+        self.scroll = self.scroll.clamp(0.0, max);
+    }
+
+    // Scroll just enough that the cursor is in view.
+    pub fn scroll_to_cursor(&mut self) {
+        let line_height = self.line_height() as f64;
+        let top = (self.cursor / ROW_BYTES) as f64 * line_height;
+        if top < self.scroll {
+            self.scroll = top;
+        } else if top + line_height > self.scroll + self.size.1 as f64 {
+            self.scroll = top + line_height - self.size.1 as f64;
+        }
+        self.clamp_scroll();
+    }
+
+    // The scroll, to the nearest pixel.
+    pub fn scroll_offset(&self) -> i64 {
+        self.scroll.round() as i64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::layout::Monospace;
+
+    fn hex(bytes : &[u8]) -> HexEditor {
+        HexEditor::new(String::from("test"), bytes.to_vec(), false, Box::new(Monospace { advance : 10.0, line_height : 20.0 }), (800,600))
+    }
+
+    fn type_str(hex : &mut HexEditor, text : &str) {
+        for character in text.chars() {
+            hex.type_char(character);
+        }
+    }
+
+    #[test]
+    fn typing_over_bytes() {
+        let mut hex = hex(&[0x00, 0x11]);
+        type_str(&mut hex, "a");
+        assert_eq!(hex.bytes(), [0xA0, 0x11]);
+        // anything that isn't a hex digit does nothing.
+        type_str(&mut hex, "xB");
+        assert_eq!(hex.bytes(), [0xAB, 0x11]);
+        assert_eq!(hex.cursor, 1);
+        assert!(hex.modified());
+        // past the end adds more.
+        type_str(&mut hex, "22ff");
+        assert_eq!(hex.bytes(), [0xAB, 0x22, 0xFF]);
+
+        // backspace only moves back when typing over.
+        hex.type_char('\u{8}');
+        assert_eq!(hex.cursor, 2);
+        assert_eq!(hex.bytes().len(), 3);
+    }
+
+    #[test]
+    fn inserting_bytes() {
+        let mut hex = hex(&[0x11, 0x22]);
+        hex.insert = true;
+        hex.cursor = 1;
+        type_str(&mut hex, "ff");
+        assert_eq!(hex.bytes(), [0x11, 0xFF, 0x22]);
+        hex.type_char('\u{8}');
+        assert_eq!(hex.bytes(), [0x11, 0x22]);
+        hex.delete();
+        assert_eq!(hex.bytes(), [0x11]);
+    }
+
+    #[test]
+    fn typing_ascii() {
+        let mut hex = hex(b"abc");
+        hex.type_char('\t');
+        assert!(hex.ascii);
+        type_str(&mut hex, "X\u{E9}Y");
+        assert_eq!(hex.bytes(), b"XYc");
+    }
+
+    #[test]
+    fn moving_by_bytes_and_rows() {
+        let mut hex = hex(&[0; 40]);
+        hex.move_cursor(CursorMovement::Down);
+        assert_eq!(hex.cursor, 16);
+        hex.move_cursor(CursorMovement::End);
+        assert_eq!(hex.cursor, 31);
+        hex.move_cursor(CursorMovement::Down);
+        assert_eq!(hex.cursor, 31);
+        hex.move_cursor(CursorMovement::DocumentEnd);
+        assert_eq!(hex.cursor, 40);
+        hex.move_cursor(CursorMovement::Home);
+        assert_eq!(hex.cursor, 32);
+        hex.move_cursor(CursorMovement::PageUp(5));
+        assert_eq!(hex.cursor, 0);
+        hex.move_cursor(CursorMovement::Left);
+        assert_eq!(hex.cursor, 0);
+    }
+
+    #[test]
+    fn clicking_either_column() {
+        let mut hex = hex(&[0; 40]);
+        // the hex starts ten characters in, after the offset, and each byte is three wide.
+        hex.click(100.0 + 7.0 * 10.0, 25.0);
+        assert_eq!((hex.cursor, hex.ascii), (18, false));
+        hex.click(100.0 + (ASCII_COLUMN + 3) as f32 * 10.0, 5.0);
+        assert_eq!((hex.cursor, hex.ascii), (3, true));
+        hex.click(0.0, 1000.0);
+        assert_eq!(hex.cursor, 40);
+    }
+
+    #[test]
+    fn rows() {
+        let hex = hex(b"0123456789abcdef\x00\xFF");
+        assert_eq!(hex.len_rows(), 2);
+        let [(_,row),(_,ascii)] = hex.row_text(0);
+        assert_eq!(row, "30 31 32 33 34 35 36 37  38 39 61 62 63 64 65 66 ");
+        assert_eq!(ascii, "0123456789abcdef");
+        assert_eq!(hex.row_text(1)[1].1, "..");
+        assert_eq!(hex.gutter_labels()[1].0, "00000010");
+    }
+}
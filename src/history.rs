@@ -0,0 +1,176 @@
+// Undo and redo. The history only remembers what changed, the editor is what
+//  applies it back to the document.
+use super::cursor::Cursor;
+
+// A range of the buffer that was replaced, at the byte offset it was when it happened.
+#[derive(Clone,Debug)]
+pub struct Change {
+    pub start : usize,
+    pub removed : String,
+    pub inserted : String
+}
+
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub enum EditKind {
+    Typing, // consecutive typing is undone all at once
+    Deleting, // and so is a run of backspaces
+    Other
+}
+
+// One undo step. The changes are in the order they were applied.
+pub struct Transaction {
+    pub changes : Vec<Change>,
+    pub cursors_before : Vec<Cursor>,
+    pub cursors_after : Vec<Cursor>,
+    kind : EditKind,
+    id : usize
+}
+
+pub struct History {
+    undo : Vec<Transaction>,
+    redo : Vec<Transaction>,
+    next_id : usize,
+    saved : Option<usize>, // id of the transaction on top of undo when the file was saved. None if that transaction is gone.
+    sealed : bool // don't group the next edit with the last one
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+impl History {
+    pub fn new() -> Self {
+        // id 0 is the document as it was opened.
+        History { undo : vec![], redo : vec![], next_id : 1, saved : Some(0), sealed : false }
+    }
+
+    fn current(&self) -> usize {
+        self.undo.last().map(|transaction| transaction.id).unwrap_or(0)
+    }
+
+    pub fn record(&mut self, changes : Vec<Change>, cursors_before : Vec<Cursor>, cursors_after : Vec<Cursor>, kind : EditKind) {
+        if changes.is_empty() {
+            return;
+        }
+        // a new edit makes everything that was undone unreachable.
+        if self.redo.drain(..).any(|transaction| Some(transaction.id) == self.saved) {
+            self.saved = None;
+        }
+
+        let current = self.current();
+        if let Some(top) = self.undo.last_mut() {
+            // group runs of typing, but never into the step the file was saved at, so undoing can get back to it.
+            if kind != EditKind::Other && top.kind == kind && !self.sealed && self.saved != Some(current) {
+                top.changes.extend(changes);
+                top.cursors_after = cursors_after;
+                return;
+            }
+        }
+
+        self.undo.push(Transaction { changes, cursors_before, cursors_after, kind, id : self.next_id });
+        self.next_id += 1;
+        self.sealed = false;
+    }
+
+    // The next edit starts a new undo step, like after the cursor moves.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    // Moves the last step over to the redo stack and returns it, so it can be reverted.
+    pub fn undo(&mut self) -> Option<&Transaction> {
+        let transaction = self.undo.pop()?;
+        self.redo.push(transaction);
+        self.sealed = true;
+        self.redo.last()
+    }
+
+    // Moves the last undone step back and returns it, so it can be applied again.
+    pub fn redo(&mut self) -> Option<&Transaction> {
+        let transaction = self.redo.pop()?;
+        self.undo.push(transaction);
+        self.sealed = true;
+        self.undo.last()
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.current());
+        self.sealed = true;
+    }
+
+    // Does the document differ from what was last saved?
+    pub fn is_modified(&self) -> bool {
+        self.saved != Some(self.current())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(history : &mut History, at : usize, text : &str, kind : EditKind) {
+        let change = Change { start : at, removed : String::new(), inserted : text.to_string() };
+        history.record(vec![change], vec![], vec![], kind);
+    }
+
+    #[test]
+    fn typing_groups_until_sealed() {
+        let mut history = History::new();
+        typed(&mut history, 0, "a", EditKind::Typing);
+        typed(&mut history, 1, "b", EditKind::Typing);
+        assert_eq!(history.undo().unwrap().changes.len(), 2);
+        assert!(history.undo().is_none());
+
+        let mut history = History::new();
+        typed(&mut history, 0, "a", EditKind::Typing);
+        history.seal();
+        typed(&mut history, 1, "b", EditKind::Typing);
+        assert_eq!(history.undo().unwrap().changes.len(), 1);
+        assert_eq!(history.undo().unwrap().changes.len(), 1);
+    }
+
+    #[test]
+    fn different_kinds_dont_group() {
+        // and other edits never group, even with each other.
+        let mut history = History::new();
+        typed(&mut history, 0, "a", EditKind::Typing);
+        typed(&mut history, 1, "", EditKind::Deleting);
+        typed(&mut history, 1, "b", EditKind::Other);
+        typed(&mut history, 2, "c", EditKind::Other);
+        assert_eq!((0..5).filter(|_| history.undo().is_some()).count(), 4);
+    }
+
+    #[test]
+    fn saved_marker() {
+        let mut history = History::new();
+        assert!(!history.is_modified());
+        typed(&mut history, 0, "a", EditKind::Typing);
+        assert!(history.is_modified());
+        history.mark_saved();
+        assert!(!history.is_modified());
+
+        // typing after a save is a new step, so undo gets back to exactly what was saved.
+        typed(&mut history, 1, "b", EditKind::Typing);
+        assert!(history.is_modified());
+        history.undo();
+        assert!(!history.is_modified());
+        history.undo();
+        assert!(history.is_modified());
+        history.redo();
+        assert!(!history.is_modified());
+    }
+
+    #[test]
+    fn saved_step_lost_to_a_new_edit() {
+        let mut history = History::new();
+        typed(&mut history, 0, "a", EditKind::Typing);
+        history.mark_saved();
+        history.undo();
+        typed(&mut history, 0, "b", EditKind::Typing);
+        history.undo();
+        // back where the file was opened, which isn't what was saved any more.
+        assert!(history.is_modified());
+    }
+}
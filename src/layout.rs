@@ -0,0 +1,272 @@
+// Measuring and wrapping text. None of this touches the gpu, whoever owns the font
+//  hands us something that implements Metrics.
+use unicode_segmentation::UnicodeSegmentation;
+
+pub trait Metrics {
+    // How far the pen moves after drawing this text.
+    fn advance(&self, text : &str) -> f32;
+    fn line_height(&self) -> f32;
+}
+
+// Every character is the same width. Handy when there is no window (tests, ci).
+pub struct Monospace {
+    pub advance : f32,
+    pub line_height : f32
+}
+impl Metrics for Monospace {
+    fn advance(&self, text : &str) -> f32 {
+        text.graphemes(true).count() as f32 * self.advance
+    }
+    fn line_height(&self) -> f32 {
+        self.line_height
+    }
+}
+
+// A placed grapheme cluster. x is relative to the start of its row.
+#[derive(Clone,Copy,Debug)]
+pub struct Glyph {
+    pub index : usize, // byte index into the line's text, where the grapheme starts
+    pub x : f32,
+    pub width : f32
+}
+
+// How a line of the document is laid out on screen. This is derived from the text in
+//  the document's buffer, and recalculated whenever that line changes.
+pub struct Line {
+    pub breaks : Vec<usize>,
+    pub glyphs : Vec<Vec<Glyph>>
+}
+impl Line {
+    pub fn new(text : &str, layout : &TextLayout) -> Self {
+        let glyphs = layout.batch_read_string(text);
+        let breaks = TextLayout::wrap_line(&glyphs, text);
+        Line { breaks, glyphs }
+    }
+    pub fn calculate(&mut self, text : &str, layout : &TextLayout) {
+        self.glyphs = layout.batch_read_string(text);
+        self.breaks = TextLayout::wrap_line(&self.glyphs, text);
+    }
+
+    // The length of the text this was laid out from.
+    pub fn text_len(&self) -> usize {
+        *self.breaks.last().unwrap()
+    }
+
+    // How far the longest row reaches.
+    pub fn width(&self) -> f32 {
+        self.glyphs.iter().filter_map(|row| row.last()).map(|glyph| glyph.x + glyph.width).fold(0.0, f32::max)
+    }
+
+    // Which row a byte index is drawn on. An index right on a wrap is the start of the next row.
+    pub fn row_of(&self, index : usize) -> usize {
+        let rows = self.glyphs.len();
+        (1..rows).take_while(|row| self.breaks[*row] <= index).last().unwrap_or(0)
+    }
+
+    // The byte index closest to an x position in a row. Clicking past the end of a wrapped row
+    //  stays on that row instead of going to the start of the next.
+    pub fn index_at(&self, row : usize, x : f32) -> usize {
+        let glyphs = &self.glyphs[row];
+        match glyphs.iter().find(|glyph| x < glyph.x + glyph.width / 2.0) {
+            Some(glyph) => glyph.index,
+            None if row + 1 < self.glyphs.len() => glyphs.last().map(|glyph| glyph.index).unwrap_or(self.breaks[row]),
+            None => self.text_len()
+        }
+    }
+
+    // The x position of a byte index in a row. Past the end of the row is the end of the row.
+    pub fn x_at(&self, row : usize, index : usize) -> f32 {
+        let glyphs = &self.glyphs[row];
+        match glyphs.iter().find(|glyph| glyph.index >= index) {
+            Some(glyph) => glyph.x,
+            None => glyphs.last().map(|glyph| glyph.x + glyph.width).unwrap_or(0.0)
+        }
+    }
+}
+
+// Where long lines wrap.
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum Wrap {
+    None, // every line is one row, and the view scrolls sideways
+    Window, // at the edge of the window
+    Column(usize) // after this many characters, so it looks the same in any window wide enough
+}
+
+pub struct TextLayout {
+    metrics : Box<dyn Metrics>,
+    width : f32, // how wide a row can get before wrapping, in pixels.
+    pub tab_width : usize, // in spaces
+    pub wrap : Wrap,
+    pub wrap_indent : bool // wrapped rows line up with the indentation of their line
+}
+impl TextLayout {
+    pub fn new(metrics : Box<dyn Metrics>, screen_size : (u32,u32)) -> Self {
+        let mut layout = TextLayout { metrics, width : 0.0, tab_width : 4, wrap : Wrap::Window, wrap_indent : false };
+        layout.resize(screen_size);
+        layout
+    }
+
+    pub fn resize(&mut self, screen_size : (u32,u32)) {
+        // -8 to give room for the cursor.
+        self.width = screen_size.0 as f32 - 8.0;
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.metrics.line_height()
+    }
+
+    // How wide a row can get before it wraps.
+    fn wrap_width(&self) -> f32 {
+        match self.wrap {
+            Wrap::None => f32::INFINITY,
+            Wrap::Window => self.width,
+            // a window thinner than the column still wraps at its edge, rather than hiding text.
+            Wrap::Column(columns) => self.column_x(columns).min(self.width)
+        }
+    }
+
+    // Where a column of characters starts, measured in spaces of the font.
+    pub fn column_x(&self, column : usize) -> f32 {
+        self.metrics.advance(" ") * column as f32
+    }
+
+    // Break a line into rows of glyphs that fit in the window. Rows break where unicode says a
+    //  line can (after spaces, hyphens, between CJK characters...), and only split a word
+    //  when it is too wide for a row by itself.
+    pub fn batch_read_string(&self, text : &str) -> Vec<Vec<Glyph>> {
+        let mut finished_glyphs : Vec<Vec<Glyph>> = vec![];
+        let mut row : Vec<Glyph> = vec![];
+        let mut x = 0.0;
+        let wrap_width = self.wrap_width();
+
+        // continuation rows start where the text after the line's indentation does.
+        let indentation = text.len() - text.trim_start().len();
+        let mut indent = 0.0;
+
+        let mut start = 0;
+        for (end,_) in unicode_linebreak::linebreaks(text) {
+            let segment = &text[start..end];
+            // spaces at the end of a segment hang off the end of the row, they never cause a wrap.
+            let word = segment.trim_end();
+            let word_width = self.measure(word, x);
+            if x + word_width > wrap_width && !row.is_empty() {
+                finished_glyphs.push(row);
+                row = vec![];
+                x = indent;
+            }
+
+            for (index,grapheme) in segment.grapheme_indices(true) {
+                let index = start + index;
+                if self.wrap_indent && index == indentation && finished_glyphs.is_empty() && x <= wrap_width / 2.0 {
+                    indent = x;
+                }
+
+                let mut width = self.glyph_width(grapheme, x);
+                // a word too wide for any row gets split wherever it runs out of room.
+                //  Always put at least one glyph on a row, or a thin window would loop forever.
+                if x + width > wrap_width && !row.is_empty() && index < start + word.len() {
+                    finished_glyphs.push(row);
+                    row = vec![];
+                    x = indent;
+                    width = self.glyph_width(grapheme, x);
+                }
+                row.push(Glyph { index, x, width });
+                x += width;
+            }
+            start = end;
+        }
+
+        // the last row, which may be empty for an empty line.
+        finished_glyphs.push(row);
+        finished_glyphs
+    }
+
+    // How wide a grapheme is, drawn at x. A tab reaches to the next tab stop.
+    fn glyph_width(&self, grapheme : &str, x : f32) -> f32 {
+        if grapheme == "\t" {
+            let stop = self.metrics.advance(" ") * self.tab_width as f32;
+            stop - x % stop
+        } else {
+            self.metrics.advance(grapheme)
+        }
+    }
+
+    // How wide some text is, starting at x.
+    fn measure(&self, text : &str, x : f32) -> f32 {
+        text.graphemes(true).fold(x, |x,grapheme| x + self.glyph_width(grapheme, x)) - x
+    }
+
+    // The byte index each row starts at, with the length of the text on the end.
+    pub fn wrap_line(glyphs : &[Vec<Glyph>], text : &str) -> Vec<usize> {
+        let mut breaks : Vec<usize> = glyphs.iter().map(|row| {
+            row.first().map(|glyph| glyph.index).unwrap_or(0)
+        }).collect();
+        breaks.push(text.len()); // makes life easier
+        breaks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // ten pixels a character, and room for ten of them in a row.
+    fn layout(wrap : Wrap) -> TextLayout {
+        let mut layout = TextLayout::new(Box::new(Monospace { advance : 10.0, line_height : 20.0 }), (108,100));
+        layout.wrap = wrap;
+        layout
+    }
+
+    #[test]
+    fn tabs_reach_the_next_stop() {
+        let mut layout = layout(Wrap::None);
+        let x = |layout : &TextLayout| layout.batch_read_string("a\tb\t\tc")[0].iter().map(|glyph| glyph.x).collect::<Vec<f32>>();
+        assert_eq!(x(&layout), vec![0.0, 10.0, 40.0, 50.0, 80.0, 120.0]);
+        layout.tab_width = 2;
+        assert_eq!(x(&layout), vec![0.0, 10.0, 20.0, 30.0, 40.0, 60.0]);
+    }
+
+    // The text of each row.
+    fn rows<'a>(layout : &TextLayout, text : &'a str) -> Vec<&'a str> {
+        let glyphs = layout.batch_read_string(text);
+        let breaks = TextLayout::wrap_line(&glyphs, text);
+        breaks.windows(2).map(|pair| &text[pair[0]..pair[1]]).collect()
+    }
+
+    #[test]
+    fn wraps_between_words() {
+        let layout = layout(Wrap::Window);
+        assert_eq!(rows(&layout, "one two three fours"), vec!["one two ", "three ", "fours"]);
+        // spaces hang off the end instead of wrapping.
+        assert_eq!(rows(&layout, "abcdefghij   k"), vec!["abcdefghij   ", "k"]);
+        assert_eq!(rows(&layout, "a well-known word"), vec!["a well-", "known word"]);
+        assert_eq!(rows(&layout, ""), vec![""]);
+    }
+
+    #[test]
+    fn splits_long_words() {
+        let layout = layout(Wrap::Window);
+        assert_eq!(rows(&layout, "abcdefghijklmnopqrstuvwxy"), vec!["abcdefghij", "klmnopqrst", "uvwxy"]);
+        assert_eq!(rows(&layout, "ab cdefghijklmnop"), vec!["ab ", "cdefghijkl", "mnop"]);
+    }
+
+    #[test]
+    fn wrap_indent() {
+        let mut layout = layout(Wrap::Window);
+        layout.wrap_indent = true;
+        let glyphs = layout.batch_read_string("  aaa bbb ccc");
+        assert_eq!(glyphs.len(), 2);
+        assert_eq!(glyphs[1][0].x, 20.0);
+        layout.wrap_indent = false;
+        assert_eq!(layout.batch_read_string("  aaa bbb ccc")[1][0].x, 0.0);
+    }
+
+    #[test]
+    fn wrap_modes() {
+        let text = "aaaa bbbb cccc";
+        assert_eq!(rows(&layout(Wrap::None), text), vec![text]);
+        assert_eq!(rows(&layout(Wrap::Column(5)), text), vec!["aaaa ", "bbbb ", "cccc"]);
+        // a column wider than the window still wraps at the window.
+        assert_eq!(rows(&layout(Wrap::Column(80)), text), vec!["aaaa bbbb ", "cccc"]);
+    }
+}
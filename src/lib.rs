@@ -0,0 +1,9 @@
+// The editing core. Nothing in here knows about windows or the gpu, so it can be
+//  driven (and tested) without a display. main.rs is the frontend on top of it.
+pub mod layout;
+pub mod clipboard;
+pub mod document;
+pub mod cursor;
+pub mod history;
+pub mod editor;
+pub mod hex;
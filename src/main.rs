@@ -1,21 +1,19 @@
 mod rect;
-mod cursor;
 mod button;
+mod system_clipboard;
 
 /*
 TODO: subscribe to file updates
-TODO: line numbers
 TODO: draw play area and margin separetly, and blit together
 TODO: fun timing and color things. like a fading cursor.
-TODO: Copy and Paste
 TODO: Alt - drag for windows (shift for window resize)
 TODO: dbus magic
  */
 
-use std::{io::{BufRead, Write}};
-use cursor::Cursor;
-use wgpu::{util::StagingBelt, Device, RenderPass};
-use wgpu_glyph::{*,ab_glyph::{self, Font, FontArc}, GlyphBrushBuilder, GlyphBrush, Section, Text, GlyphPositioner, SectionGeometry};
+use std::time::{Duration, Instant};
+use manip::{clipboard::ClipboardKind, cursor::{CursorMovement, Deletion}, document::{Document, Encoding, Indent, LineEnding, SaveError}, editor::{Editor, LineNumbers, SelectUnit, Status}, hex::HexEditor, layout};
+use wgpu::{util::StagingBelt, Device};
+use wgpu_glyph::{ab_glyph::{self, Font, FontArc, ScaleFont}, GlyphBrushBuilder, GlyphBrush, Section, Text, GlyphPositioner, SectionGeometry};
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
@@ -31,28 +29,35 @@ fn rgb(color : Color) -> (f32,f32,f32) {
     ((color.0 as f32/255.0).powf(2.2),(color.1 as f32/255.0).powf(2.2),(color.2 as f32/255.0).powf(2.2))
 }
 
-static bg_color : Color = (251, 242, 207);
-static text_color : Color = (61, 60, 66);
-static margin_bg_color : Color = (120, 149, 178);
-static save_ind_saved_color : Color = (161, 194, 152);
-static save_ind_nsaved_color : Color = (250, 112, 112);
-static exit_btn_color : Color = save_ind_nsaved_color;
-
-
-pub struct Line {
-    text : String,
-    breaks : Vec<usize>,
-    glyphs : Vec<Vec<SectionGlyph>>
+static BG_COLOR : Color = (251, 242, 207);
+static TEXT_COLOR : Color = (61, 60, 66);
+static MARGIN_BG_COLOR : Color = (120, 149, 178);
+static SAVE_IND_SAVED_COLOR : Color = (161, 194, 152);
+static SAVE_IND_NSAVED_COLOR : Color = (250, 112, 112);
+static EXIT_BTN_COLOR : Color = SAVE_IND_NSAVED_COLOR;
+static ERROR_BG_COLOR : Color = (214, 90, 90);
+static SELECTION_COLOR : Color = (226, 220, 200);
+static RULER_COLOR : Color = (230, 222, 190);
+static GUTTER_BG_COLOR : Color = (240, 230, 195);
+static GUTTER_TEXT_COLOR : Color = (150, 145, 130);
+
+static WRAP_COLUMN : usize = 80;
+static STATUS_HEIGHT : u32 = 20;
+
+
+// Measures text with the font the glyph brush draws with, so the editor's layout
+//  matches what ends up on screen.
+struct FontMetrics {
+    font : FontArc,
+    font_size : f32
 }
-impl Line {
-    pub fn new(text : String, glyph_brush : &GlyphBrush<()>, font_scale : f32, screen_size : (u32,u32) ) -> Self {
-        let glyphs = State::batch_read_string(&glyph_brush, font_scale, screen_size, &text);
-        let breaks = State::wrap_line(&glyphs, &text);
-        Line { text, breaks, glyphs}
+impl layout::Metrics for FontMetrics {
+    fn advance(&self, text : &str) -> f32 {
+        let font = self.font.as_scaled(self.font_size);
+        text.chars().map(|character| font.h_advance(font.glyph_id(character))).sum()
     }
-    pub fn calculate(&mut self, glyph_brush : &GlyphBrush<()>, font_scale : f32, screen_size : (u32,u32) ) {
-        self.glyphs = State::batch_read_string(&glyph_brush, font_scale, screen_size, &self.text);
-        self.breaks = State::wrap_line(&self.glyphs, &self.text);
+    fn line_height(&self) -> f32 {
+        self.font_size
     }
 }
 
@@ -61,32 +66,42 @@ struct TopMargin {
     left_icon : rect::Rect,
     file_name : String,
     name_width : u32,
-    exit_button : button::Button
+    exit_button : button::Button,
+    error : Option<String> // shown instead of the file name, until the next save works or escape
 }
 impl TopMargin {
     fn new(device : &Device, glyph_brush : &GlyphBrush<()>, screen_size : (u32,u32), file_name :String, font_size : f32) -> Self {
         let margin_height = 20;
-        let margin_rect = rect::Rect::new(device,screen_size,(screen_size.0,margin_height), (0,0), (0,0), rgb(margin_bg_color));
-        let left_icon = rect::Rect::new(device, screen_size,(16,16), (2,2), (0,0), rgb(save_ind_saved_color));
+        let margin_rect = rect::Rect::new(device,screen_size,(screen_size.0,margin_height), (0,0), (0,0), rgb(MARGIN_BG_COLOR));
+        let left_icon = rect::Rect::new(device, screen_size,(16,16), (2,2), (0,0), rgb(SAVE_IND_SAVED_COLOR));
 
         let exit_button = button::ButtonBuilder::new(screen_size).size((16,16)).pos((screen_size.0 as i64-20, 2))
-            .color(rgb(exit_btn_color)).build(device);
+            .color(rgb(EXIT_BTN_COLOR)).build(device);
 
         let name_width = get_text_width(glyph_brush,&file_name, font_size);
-        
-        TopMargin { rect: margin_rect, left_icon, file_name: file_name,exit_button, name_width }
+
+        TopMargin { rect: margin_rect, left_icon, file_name,exit_button, name_width, error : None }
     }
 
-    fn get_section<'a>(&'a self,font_size : f32) -> Section<'a> {
-        let layout = Layout::default_single_line();
-        let pos = ((self.rect.px_size.0 as i64 / 2 - self.name_width as i64 / 2) as f32, 2.0);
+    fn get_section(&self,font_size : f32) -> Section<'_> {
+        let f_text_color = rgb(TEXT_COLOR);
+        // an error goes right after the save indicator, since it's probably longer than the file name.
+        if let Some(error) = &self.error {
+            let text = Text::new(error).with_color([f_text_color.0,f_text_color.1,f_text_color.2,1.1]).with_scale(font_size);
+            return Section {
+                screen_position: (24.0, 2.0),
+                bounds: (self.rect.px_size.0.saturating_sub(48) as f32, self.rect.px_size.1 as f32),
+                text: vec![text],
+                layout: wgpu_glyph::Layout::default_single_line(),
+            };
+        }
 
-        let f_text_color = rgb(text_color);
+        let pos = ((self.rect.px_size.0 as i64 / 2 - self.name_width as i64 / 2) as f32, 2.0);
         let text = Text::new(&self.file_name).with_color([f_text_color.0,f_text_color.1,f_text_color.2,1.1]).with_scale(font_size);
 
         // Fixes a funny rendering bug when the screen gets too thin (width).
         let width = if self.rect.px_size.0 > self.name_width { self.rect.px_size.0 } else { self.name_width };
-        
+
         Section {
             screen_position: pos,
             bounds: (width as f32, self.rect.px_size.1 as f32),
@@ -95,13 +110,17 @@ impl TopMargin {
         }
     }
 
-    fn draw<'a>(&'a mut self,device : &wgpu::Device, render_pass : &mut wgpu::RenderPass<'a>, glyph_brush : &mut GlyphBrush<()>,font_size : f32, modified : bool) {
-        let color : Color = if modified == true { save_ind_nsaved_color } else { save_ind_saved_color };
+    fn draw<'a>(&'a mut self,device : &wgpu::Device, render_pass : &mut wgpu::RenderPass<'a>, modified : bool) {
+        let color : Color = if modified { SAVE_IND_NSAVED_COLOR } else { SAVE_IND_SAVED_COLOR };
         self.left_icon.set_color(device, rgb(color));
+        self.rect.set_color(device, rgb(if self.error.is_some() { ERROR_BG_COLOR } else { MARGIN_BG_COLOR }));
         self.rect.draw(render_pass);
         self.left_icon.draw(render_pass);
         self.exit_button.draw(render_pass);
+    }
 
+    // The file name goes on top of everything else, so it's queued after the text is drawn.
+    fn queue_text(&self, glyph_brush : &mut GlyphBrush<()>, font_size : f32) {
         glyph_brush.queue(self.get_section(font_size));
     }
 
@@ -114,13 +133,149 @@ impl TopMargin {
 
 }
 
-pub fn get_text_width(glyph_brush : &GlyphBrush<()>, text : &String, font_size : f32) -> u32 {
+// The line numbers down the left of the text.
+struct Gutter {
+    rect : rect::Rect
+}
+impl Gutter {
+    fn new(device : &Device, screen_size : (u32,u32)) -> Self {
+        Gutter { rect : rect::Rect::new(device, screen_size, (0,screen_size.1), (0,0), (0,0), rgb(GUTTER_BG_COLOR)) }
+    }
+
+    // The gutter gets wider as the file gets more lines.
+    fn update(&mut self, device : &Device, screen_size : (u32,u32), width : u32, top : i64) {
+        self.rect.set_rect(device, screen_size, 0, top, width, screen_size.1);
+    }
+
+    fn draw<'a>(&'a self, render_pass : &mut wgpu::RenderPass<'a>) {
+        if self.rect.px_size.0 > 0 {
+            self.rect.draw(render_pass);
+        }
+    }
+
+    // Each number lines up on the right, a space away from the text.
+    fn queue_text(&self, glyph_brush : &mut GlyphBrush<()>, labels : &[(String,i64)], space : f32, top : i64, font_size : f32) {
+        let color = rgb(GUTTER_TEXT_COLOR);
+        let right = self.rect.px_size.0 as f32 - space;
+        for (number,y) in labels {
+            glyph_brush.queue(Section {
+                screen_position : (right, (y + top) as f32),
+                bounds : (right, font_size),
+                text : vec![Text::new(number).with_color([color.0,color.1,color.2,1.0]).with_scale(font_size)],
+                layout : wgpu_glyph::Layout::default_single_line().h_align(wgpu_glyph::HorizontalAlign::Right),
+            });
+        }
+    }
+}
+
+// The parts of the status bar that do something when clicked.
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+enum StatusField {
+    Encoding,
+    LineEnding,
+    FinalNewline,
+    Indent,
+    HexInsert // typing over bytes or in between them
+}
+
+// Along the bottom of the window. Where the cursor is on the left, and how the file is saved on the right.
+struct StatusBar {
+    rect : rect::Rect,
+    left : String,
+    right : Vec<(String,u32,Option<StatusField>)>, // text, where it starts, and what clicking it does
+}
+impl StatusBar {
+    fn new(device : &Device, screen_size : (u32,u32)) -> Self {
+        let rect = rect::Rect::new(device, screen_size, (screen_size.0,STATUS_HEIGHT), (0,screen_size.1 as i64 - STATUS_HEIGHT as i64), (0,0), rgb(MARGIN_BG_COLOR));
+        StatusBar { rect, left : String::new(), right : vec![] }
+    }
+
+    fn update(&mut self, device : &Device, screen_size : (u32,u32)) {
+        self.rect.set_rect(device, screen_size, 0, screen_size.1 as i64 - STATUS_HEIGHT as i64, screen_size.0, STATUS_HEIGHT);
+    }
+
+    // Lay out the fields again from what the editor says. Called whenever the cursors change.
+    fn set_status(&mut self, status : Status, glyph_brush : &GlyphBrush<()>, font_size : f32) {
+        self.left = format!("Ln {}, Col {}", status.line, status.column);
+        if status.selected > 0 {
+            self.left += &format!(" ({} selected)", status.selected);
+        }
+        if status.cursors > 1 {
+            self.left += &format!("   {} cursors", status.cursors);
+        }
+        self.left += &format!("   {} lines", status.lines);
+
+        let mut fields = vec![];
+        if status.read_only {
+            fields.push((String::from("Read only"), None));
+        }
+        let indent = if status.indent.soft { "Spaces" } else { "Tab size" };
+        fields.extend([
+            (status.mode, None),
+            (format!("{}: {}", indent, status.indent.width), Some(StatusField::Indent)),
+            (status.encoding.name().to_string(), Some(StatusField::Encoding)),
+            (status.line_ending.name().to_string(), Some(StatusField::LineEnding))
+        ]);
+        // most files end with a newline, so only say something when this one doesn't.
+        if !status.final_newline {
+            fields.push((String::from("No final newline"), Some(StatusField::FinalNewline)));
+        }
+        self.set_fields(fields, glyph_brush, font_size);
+    }
+
+    // The same, for editing bytes. Where the cursor is, as an offset, and how typing goes in.
+    fn set_hex_status(&mut self, hex : &HexEditor, glyph_brush : &GlyphBrush<()>, font_size : f32) {
+        self.left = format!("Offset {:X} ({})   {} bytes", hex.cursor, hex.cursor, hex.bytes().len());
+        let fields = vec![
+            (String::from(if hex.ascii { "Hex, typing ASCII" } else { "Hex" }), None),
+            (String::from(if hex.insert { "Insert" } else { "Overwrite" }), Some(StatusField::HexInsert))
+        ];
+        self.set_fields(fields, glyph_brush, font_size);
+    }
+
+    fn set_fields(&mut self, fields : Vec<(String,Option<StatusField>)>, glyph_brush : &GlyphBrush<()>, font_size : f32) {
+        // right to left from the edge of the window.
+        let mut x = self.rect.px_size.0.saturating_sub(8);
+        self.right = fields.into_iter().rev().map(|(text,field)| {
+            x = x.saturating_sub(get_text_width(glyph_brush, &text, font_size));
+            let start = x;
+            x = x.saturating_sub(16);
+            (text,start,field)
+        }).collect();
+    }
+
+    // What was clicked, if anything. x is from the left of the window.
+    fn field_at(&self, x : u32, glyph_brush : &GlyphBrush<()>, font_size : f32) -> Option<StatusField> {
+        self.right.iter().find(|(text,start,_)| x >= *start && x < start + get_text_width(glyph_brush, text, font_size))
+            .and_then(|(_,_,field)| *field)
+    }
+
+    fn draw<'a>(&'a self, render_pass : &mut wgpu::RenderPass<'a>) {
+        self.rect.draw(render_pass);
+    }
+
+    fn queue_text(&self, glyph_brush : &mut GlyphBrush<()>, font_size : f32) {
+        let color = rgb(TEXT_COLOR);
+        let y = self.rect.get_pos().1 as f32 + 2.0;
+        let texts = std::iter::once((&self.left,4)).chain(self.right.iter().map(|(text,x,_)| (text,*x)));
+        for (text,x) in texts {
+            glyph_brush.queue(Section {
+                screen_position : (x as f32, y),
+                bounds : (self.rect.px_size.0 as f32, STATUS_HEIGHT as f32),
+                text : vec![Text::new(text).with_color([color.0,color.1,color.2,1.0]).with_scale(font_size)],
+                layout : wgpu_glyph::Layout::default_single_line(),
+            });
+        }
+    }
+}
+
+pub fn get_text_width(glyph_brush : &GlyphBrush<()>, text : &str, font_size : f32) -> u32 {
     let font = &glyph_brush.fonts()[0];
     let layout = wgpu_glyph::Layout::default_single_line();
-    
-    let mut wgpu_texts = vec![ Text::new(text.as_str()).with_scale(font_size) ];
+
+    let wgpu_texts = vec![ Text::new(text).with_scale(font_size) ];
     let sec_geom = SectionGeometry { screen_position: (0.0,0.0), ..Default::default() };
-    let mut sec_glyphs = layout.calculate_glyphs(&[font], &sec_geom , wgpu_texts.as_slice());
+    let sec_glyphs = layout.calculate_glyphs(&[font], &sec_geom , wgpu_texts.as_slice());
 
     // If the actual width of the text is bigger than unbounded (SectionGeometry default), then we have a probelm.
 
@@ -129,7 +284,7 @@ pub fn get_text_width(glyph_brush : &GlyphBrush<()>, text : &String, font_size :
     }else { 0 }
 }
 
-// The graphical state of the window.
+// The graphical state of the window. The document itself lives in the editor.
 pub struct State {
     surface : wgpu::Surface,
     device : wgpu::Device,
@@ -141,23 +296,20 @@ pub struct State {
 
     rect_pipeline : rect::RectPipeline,
 
-    file_name : String,
-    lines : Vec<Line>,
+    editor : Editor,
+    hex : Option<HexEditor>, // editing the file as bytes instead, for files that aren't text
     font_scale : f32,
 
-    cursors : Vec<Cursor>,
+    cursor_rects : Vec<rect::Rect>, // one per cursor in the editor
+    selection_rects : Vec<rect::Rect>, // one per row of selected text
+    ruler_rect : Option<rect::Rect>, // where text wraps, when it wraps at a column
+    gutter : Gutter,
+    status_bar : StatusBar,
     rectangles: Vec<rect::Rect>,
 
     top_margin : TopMargin,
-    modified : bool, // Has the document been modified?
-
-    scroll : f64
 }
 
-#[derive(Clone,Copy)]
-pub enum CursorMovement {
-    Up,Down,Left,Right
-}
 // colors: https://colorhunt.co/palette/100f0f0f3d3ee2dcc8f1f1f1
 
 impl State {
@@ -200,113 +352,36 @@ impl State {
         // /home/david/.local/share/fonts/Vulf_Mono-Light_Italic_web.ttf
         // ../Monocraft.otf
         let vulf = ab_glyph::FontArc::try_from_slice(include_bytes!("../Monocraft.otf")).unwrap();
-        let glyph_brush = GlyphBrushBuilder::using_font(vulf).build(&device, wgpu::TextureFormat::Bgra8UnormSrgb);
+        let glyph_brush = GlyphBrushBuilder::using_font(vulf.clone()).build(&device, wgpu::TextureFormat::Bgra8UnormSrgb);
         let staging_belt = wgpu::util::StagingBelt::new(1024);
         let font_size = 16.0;
 
-        let file_lines : Vec<String> = {// open the file ---------------------------------|
-            let path = std::path::Path::new(&file_name);
-            
-            match std::fs::OpenOptions::new().read(true).open(path){
-                Ok(file) => {
-                    let mut text : Vec<String> = vec![];
-                    let reader = std::io::BufReader::new(file);
-                    for line in reader.lines() { // from_utf8_lossy for binary files. Want differnt mode!
-                        // if the file contains bad text, dump the text so far, report error, and break.
-                        let line = if let Ok(line) = line {line} else {text = vec![]; println!("Failed to read file : contains invalid utf-8!"); break;};
-                        
-                        // copy the file into text.
-                        text.push(line);
-                    }
-
-                    if text.len() == 0 { // If the file is emtpy, add an empty line
-                        text.push(String::new())
-                    }
-                    
-                    // close the file by dropping the File object.
-                    text // Return to file_lines <---------------------------------------------|
-                },
-                Err(e ) => {
-                    //TODO: Handle some of the errors from e
-                    if let std::io::ErrorKind::NotFound = e.kind() {
-                        vec![String::new()]
-                    } else {
-                        panic!("Error opening file! : {}",e);
-                    }
-                },
-            }
-            
-        };
-
-        let mut lines : Vec<Line> = vec![];
-        for line in file_lines {
-            lines.push( Line::new(line,&glyph_brush, font_size, (size.width,size.height)) );
-        }
+        let top_margin = TopMargin::new(&device, &glyph_brush,(size.width,size.height), file_name.clone(),font_size);
+        let gutter = Gutter::new(&device, (size.width,size.height));
+        let status_bar = StatusBar::new(&device, (size.width,size.height));
 
+        let metrics = FontMetrics { font : vulf, font_size };
+        let mut editor = Editor::new(file_name, Box::new(metrics), (size.width,size.height.saturating_sub(top_margin.rect.px_size.1 + STATUS_HEIGHT)));
+        editor.set_clipboard(Box::new(system_clipboard::SystemClipboard::new()));
         let rect_pipeline = rect::RectPipeline::new(&device, config.format);
 
         // let cursor : (usize,usize) = (0,0);
         let rectangles = vec![];
         // create a bunch of rectangles
 
-        let top_margin = TopMargin::new(&device, &glyph_brush,(size.width,size.height), file_name.clone(),font_size);
-        
-
-        let mut state = Self { surface, device, queue, config, size, glyph_brush, staging_belt, rect_pipeline, rectangles, font_scale: font_size,file_name,cursors : vec![], scroll : 0.0 , lines, top_margin, modified:false };
-
-        let cursor : cursor::Cursor = cursor::Cursor::new(&state, (0,0));
-        state.cursors.push(cursor);
-
+        let mut state = Self { surface, device, queue, config, size, glyph_brush, staging_belt, rect_pipeline, rectangles, font_scale: font_size, editor, hex : None, cursor_rects : vec![], selection_rects : vec![], ruler_rect : None, gutter, status_bar, top_margin };
+        // a file that can't be read as text is probably meant to be edited as bytes. That includes anything
+        //  with nul bytes, which is how most binaries show, even the ones that happen to be valid utf-8.
+        if state.editor.document.read_only() {
+            state.toggle_hex();
+        } else {
+            state.show_document_warning();
+        }
+        state.show_clipboard_error();
+        state.update_cursors();
         state
     }
 
-    fn wrap_line( glyphs : &Vec<Vec<SectionGlyph>>, text : &String) -> Vec<usize> {
-        // a list of pairs of a string and its breaks. Each break is a seperator of lines from word wrap.
-        let mut acc = 0;
-        let mut breaks : Vec<usize> = glyphs.iter().map(|x| {
-            acc += x.len();
-            acc
-        }).collect();
-        breaks.insert(0,0); // makes life easier
-        breaks
-    }
-    
-    // Read a big string, and generate the needed sections
-    fn batch_read_string(glyph_brush : &GlyphBrush<()>,font_size : f32, screen_size : (u32,u32), text : &String) -> Vec<Vec<SectionGlyph>> {
-        // TODO: Custom layout that supports single-word character wrapping. Pain awaits.
-        let font = &glyph_brush.fonts()[0]; // TODO: Font managing (Low priority)
-        let layout = wgpu_glyph::Layout::default_single_line();
-        
-        let mut wgpu_texts = vec![ Text::new(text.as_str()).with_scale(font_size) ];
-                                                                                            // -8 to give room for the cursor.
-        let sec_geom = SectionGeometry { screen_position: (0.0,0.0), bounds: (screen_size.0 as f32 - 8.0,screen_size.1 as f32) };
-        let mut sec_glyphs = layout.calculate_glyphs(&[font], &sec_geom , wgpu_texts.as_slice());
-
-        let mut finished_glyphs : Vec<Vec<SectionGlyph>> = vec![];
-        
-        let mut i = 0;
-        let mut acc_length = 0;
-
-        while acc_length + sec_glyphs.len() != text.len() {
-            // --------------- Archive this text. It is the right length ----------------
-            //                                                               v--- because it is the right operand of '..', it is exclusive.
-            let slice = acc_length..acc_length+sec_glyphs.len();
-            wgpu_texts[i] = Text::new(&text[slice]).with_scale(font_size);
-            finished_glyphs.push( sec_glyphs);
-            acc_length += wgpu_texts[i].text.len();
-
-            let potential_text = Text::new(&text[acc_length..]).with_scale(font_size);
-            sec_glyphs = layout.calculate_glyphs(&[font], &sec_geom, &[potential_text]);
-            wgpu_texts.push( potential_text );
-
-            i += 1;
-        }
-
-        // compute last string to glyph
-        finished_glyphs.push(sec_glyphs);
-        return finished_glyphs;
-    }
-
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
 		if new_size.width > 0 && new_size.height > 0 {
             self.config.width = new_size.width;
@@ -317,56 +392,75 @@ impl State {
             self.surface.configure(&self.device, &self.config);
 
             // recalculate word wrap
-            for line in &mut self.lines {
-                line.calculate(&self.glyph_brush, self.font_scale, (self.size.width,self.size.height));
+            let text_size = self.text_size();
+            self.editor.resize(text_size);
+            if let Some(hex) = &mut self.hex {
+                hex.resize(text_size);
             }
 
             // recalculate rectangles
-            // self.cursor.update_rect(&self.device,(new_size.width,new_size.height));
-            for cursor in &mut self.cursors {
-                cursor.update_screen_size(&self.device, (new_size.width,new_size.height));
-            }
-
             for rect in &mut self.rectangles{
                 rect.update_rect(&self.device, (new_size.width,new_size.height));
             }
 
             // self.top_margin.rect.update_rect(&self.device, (new_size.width,new_size.height));
             self.top_margin.update(&self.device,(new_size.width,new_size.height));
+            self.status_bar.update(&self.device,(new_size.width,new_size.height));
+            self.update_cursors();
         }
 	}
 
+    // Between the top margin and the status bar, where the gutter and text go.
+    fn text_size(&self) -> (u32,u32) {
+        (self.size.width, self.size.height.saturating_sub(self.top_margin.rect.px_size.1 + STATUS_HEIGHT))
+    }
 
-    fn save_file(&mut self) {
-        // Attempt to open file.
-        let path = std::path::Path::new(&self.file_name);
-        println!("Opening {:?}",path);
-        //  Like Open("file", 'w') in C, I think.
-        let mut file = match std::fs::OpenOptions::new().write(true).truncate(true).create(true).open(path) {
-            // let mut file = match std::fs::OpenOptions::new().truncate(true).open(path) {
-            Ok(file) => file,
-            Err(e) => {
-                // TODO: don't panic, but tell the user failed to open file, graphically.
-                println!("{:?}", e);
-
-                todo!();
-            },
+    // Switch between editing the file as text and as bytes. The bytes are whatever saving the text
+    //  would write, and going back reads them as text again, the same as opening a file.
+    fn toggle_hex(&mut self) {
+        match self.hex.take() {
+            Some(hex) => {
+                let document = Document::from_bytes(hex.file_name.clone(), hex.bytes());
+                self.editor.set_document(document, hex.modified());
+                self.show_document_warning();
+            }
+            None => match self.editor.document.to_bytes() {
+                Ok(bytes) => {
+                    let metrics = FontMetrics { font : self.glyph_brush.fonts()[0].clone(), font_size : self.font_scale };
+                    self.hex = Some(HexEditor::new(self.editor.document.file_name.clone(), bytes, self.editor.modified(), Box::new(metrics), self.text_size()));
+                    self.top_margin.error = None;
+                }
+                // the text can't become bytes, so there's nothing to show.
+                Err(e) => self.top_margin.error = Some(e.to_string())
+            }
+        }
+        self.update_cursors();
+    }
+
+    // Anything about how the text was read that saving would change, or that stops it being saved.
+    fn show_document_warning(&mut self) {
+        let document = &self.editor.document;
+        self.top_margin.error = if document.read_only() {
+            Some(SaveError::ReadOnly.to_string())
+        } else if document.mixed_line_endings() {
+            Some(format!("Mixed line endings, saving will make them all {}", document.line_ending.name()))
+        } else if document.encoding == Encoding::Windows1252 {
+            // it's the only encoding that gets guessed, rather than recognised.
+            Some(String::from("Not valid UTF-8, opened as Windows-1252"))
+        } else {
+            None
         };
+    }
 
-        // erase file, and write to it.
-        let mut i : usize = 0;
-        let len = self.lines.len();
-        for Line{text,..} in &self.lines {
-            // TODO: handle these
-            file.write(text.as_bytes());
-            i+=1;
-            if i != len {
-                file.write(&['\n' as u8]);
-            }
+    // Has whichever one is showing changed since the last save?
+    fn modified(&self) -> bool {
+        match &self.hex {
+            Some(hex) => hex.modified(),
+            None => self.editor.modified()
         }
-        self.modified = false;
     }
-	fn input(&mut self, event : &WindowEvent) -> bool {
+
+	fn input(&mut self, _event : &WindowEvent) -> bool {
         false
     }
 
@@ -374,21 +468,323 @@ impl State {
 
     }
 
-    fn move_cursor(&mut self, direction : CursorMovement) {
-        for cursor in &mut self.cursors {
-            let refs : Vec<&String> = self.lines.iter().map(|x| &x.text).collect();
-            cursor.move_cursor(&refs, direction);
-            cursor.update_cursor(&self.device, &self.glyph_brush, self.scroll as i64 * self.font_scale as i64, &self.lines);
+    // Move the cursor and selection rectangles to where the editor's cursors are.
+    fn update_cursors(&mut self) {
+        let screen_size = (self.size.width,self.size.height);
+        let top_margin_offset = self.top_margin.rect.px_size.1 as i64;
+        let color = rgb(MARGIN_BG_COLOR);
+        let selection_color = rgb(SELECTION_COLOR);
+
+        if let Some(hex) = &self.hex {
+            let (x,y,w,h) = hex.cursor_rect();
+            self.cursor_rects = vec![rect::Rect::new(&self.device, screen_size, (w,h), (x,y+top_margin_offset), (0,0), color)];
+            self.selection_rects = hex.selection_rects().into_iter().map(|(x,y,w,h)| {
+                rect::Rect::new(&self.device, screen_size, (w,h), (x,y+top_margin_offset), (0,0), selection_color)
+            }).collect();
+            self.gutter.update(&self.device, screen_size, hex.text_x() as u32, top_margin_offset);
+            self.status_bar.set_hex_status(hex, &self.glyph_brush, self.font_scale);
+            self.ruler_rect = None;
+            return;
+        }
+
+        self.cursor_rects.truncate(self.editor.cursors.len());
+        while self.cursor_rects.len() < self.editor.cursors.len() {
+            self.cursor_rects.push(rect::Rect::new(&self.device,screen_size, (1,1),(0,0), (0,0),color));
+        }
+
+        for (cursor,rect) in self.editor.cursors.iter().zip(&mut self.cursor_rects) {
+            let (x,y,w,h) = self.editor.cursor_rect(cursor);
+            rect.set_offset(&self.device, (0,top_margin_offset));
+            rect.set_rect(&self.device, screen_size, x, y, w, h);
         }
-        
+
+        // selections change size all the time, so just make new ones.
+        self.selection_rects = self.editor.selection_rects().into_iter().map(|(x,y,w,h)| {
+            rect::Rect::new(&self.device, screen_size, (w,h), (x,y+top_margin_offset), (0,0), selection_color)
+        }).collect();
+
+        self.gutter.update(&self.device, screen_size, self.editor.text_x() as u32, top_margin_offset);
+        self.status_bar.set_status(self.editor.status(), &self.glyph_brush, self.font_scale);
+
+        // the ruler moves when the view scrolls sideways.
+        self.ruler_rect = self.editor.ruler_x().map(|x| {
+            rect::Rect::new(&self.device, screen_size, (1,screen_size.1), (x,top_margin_offset), (0,0), rgb(RULER_COLOR))
+        });
+    }
+
+    // A save that fails turns the top margin into a banner saying why.
+    fn save_file(&mut self) {
+        let saved = match &mut self.hex {
+            Some(hex) => hex.save_file(),
+            None => self.editor.save_file()
+        };
+        self.top_margin.error = saved.err().map(|e| e.to_string());
+    }
+
+    // Clicking the encoding or line endings goes to the next one, and the indent switches between tabs and spaces.
+    fn click_status(&mut self, x : u32) {
+        match self.status_bar.field_at(x, &self.glyph_brush, self.font_scale) {
+            Some(StatusField::Encoding) => {
+                let encoding = match self.editor.document.encoding {
+                    Encoding::Utf8 => Encoding::Utf8Bom,
+                    Encoding::Utf8Bom => Encoding::Utf16Le,
+                    Encoding::Utf16Le => Encoding::Utf16Be,
+                    Encoding::Utf16Be => Encoding::Windows1252,
+                    Encoding::Windows1252 => Encoding::Utf8
+                };
+                self.editor.set_encoding(encoding);
+            }
+            Some(StatusField::LineEnding) => return self.cycle_line_ending(),
+            Some(StatusField::FinalNewline) => return self.toggle_final_newline(),
+            Some(StatusField::Indent) => {
+                let indent = self.editor.document.indent;
+                self.editor.set_indent(Indent { soft : !indent.soft, ..indent });
+            }
+            Some(StatusField::HexInsert) => return self.toggle_insert(),
+            None => return
+        }
+        self.update_cursors();
+    }
+    // Converts the whole file to the next kind of line ending when it's saved.
+    fn cycle_line_ending(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        let line_ending = match self.editor.document.line_ending {
+            LineEnding::Lf => LineEnding::CrLf,
+            LineEnding::CrLf => LineEnding::Cr,
+            LineEnding::Cr => LineEnding::Lf
+        };
+        self.editor.set_line_ending(line_ending);
+        self.update_cursors();
+    }
+    fn toggle_final_newline(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.set_final_newline(!self.editor.document.final_newline);
+        self.update_cursors();
+    }
+    // Tab stops two, four, then eight columns apart.
+    fn cycle_indent_width(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        let indent = self.editor.document.indent;
+        let width = match indent.width {
+            2 => 4,
+            4 => 8,
+            _ => 2
+        };
+        self.editor.set_indent(Indent { width, ..indent });
+        self.update_cursors();
+    }
+    fn center_cursor(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.center_cursor();
+        self.update_cursors();
+    }
+    fn toggle_wrap(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        // around the modes: the window's edge, the wrap column, then not at all.
+        let wrap = match self.editor.layout.wrap {
+            layout::Wrap::Window => layout::Wrap::Column(WRAP_COLUMN),
+            layout::Wrap::Column(_) => layout::Wrap::None,
+            layout::Wrap::None => layout::Wrap::Window
+        };
+        self.editor.set_wrap(wrap);
+        self.update_cursors();
+    }
+    // Numbered from the top, counting out from the cursor, then none at all.
+    fn cycle_line_numbers(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        let line_numbers = match self.editor.line_numbers {
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Off,
+            LineNumbers::Off => LineNumbers::Absolute
+        };
+        self.editor.set_line_numbers(line_numbers);
+        self.update_cursors();
+    }
+    // Wrapped rows start under the line's indentation, or at the left edge.
+    fn toggle_wrap_indent(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.set_wrap_indent(!self.editor.layout.wrap_indent);
+        self.update_cursors();
+    }
+    // Up and down over whole lines, or a wrapped row at a time.
+    fn toggle_logical_lines(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.logical_lines = !self.editor.logical_lines;
+        self.update_cursors();
+    }
+    fn move_cursor(&mut self, direction : CursorMovement, select : bool) {
+        match &mut self.hex {
+            Some(hex) => hex.move_cursor(direction),
+            None => self.editor.move_cursor(direction, select)
+        }
+        self.show_clipboard_error();
+        self.update_cursors();
     }
     fn insert_cursor(&mut self, character : char) {
-        // the cursor is an index. backspace removes the character before the cursor.
-        self.modified = true;
-        for cursor in &mut self.cursors {
-            cursor.insert_text(&self.glyph_brush,&mut self.lines, character);
-            cursor.update_cursor(&self.device, &self.glyph_brush, self.scroll as i64  * self.font_scale as i64, &self.lines);
+        match &mut self.hex {
+            Some(hex) => hex.type_char(character),
+            None => self.editor.insert_cursor(character)
+        }
+        self.update_cursors();
+    }
+    fn delete(&mut self, deletion : Deletion) {
+        match &mut self.hex {
+            Some(hex) => match deletion {
+                Deletion::Forward | Deletion::WordRight => hex.delete(),
+                Deletion::WordLeft => hex.type_char('\u{8}'),
+                Deletion::Line => {}
+            },
+            None => self.editor.delete(deletion)
+        }
+        self.update_cursors();
+    }
+    fn outdent(&mut self) {
+        match &mut self.hex {
+            Some(hex) => hex.switch_column(),
+            None => self.editor.outdent()
+        }
+        self.update_cursors();
+    }
+    // Insert, for typing between bytes instead of over them.
+    fn toggle_insert(&mut self) {
+        if let Some(hex) = &mut self.hex {
+            hex.insert = !hex.insert;
+        }
+        self.update_cursors();
+    }
+    fn copy(&mut self) {
+        if self.hex.is_none() {
+            self.editor.copy();
+            self.show_clipboard_error();
+        }
+    }
+    fn cut(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.cut();
+        self.show_clipboard_error();
+        self.update_cursors();
+    }
+    // Letting go of the mouse copies the selection for middle click.
+    fn end_drag(&mut self) {
+        self.editor.end_drag();
+        self.show_clipboard_error();
+    }
+    // Copying failing is worth a banner, like a save failing.
+    fn show_clipboard_error(&mut self) {
+        if let Some(error) = self.editor.clipboard_error() {
+            self.top_margin.error = Some(error);
+        }
+    }
+    fn paste(&mut self, kind : ClipboardKind) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.paste(kind);
+        self.update_cursors();
+    }
+    fn add_cursor(&mut self, pos : (usize,usize)) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.add_cursor(pos);
+        self.update_cursors();
+    }
+    fn add_cursor_vertical(&mut self, up : bool) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.add_cursor_vertical(up);
+        self.update_cursors();
+    }
+    fn add_next_occurrence(&mut self) {
+        if self.hex.is_some() {
+            return;
         }
+        self.editor.add_next_occurrence();
+        self.update_cursors();
+    }
+    fn collapse_cursors(&mut self) {
+        self.editor.collapse_cursors();
+        self.update_cursors();
+    }
+
+    // The document position under a point in the window.
+    fn text_pos(&self, window_pos : (u32,u32)) -> (usize,usize) {
+        let top_margin_offset = self.top_margin.rect.px_size.1 as f32;
+        self.editor.hit_test(window_pos.0 as f32, window_pos.1 as f32 - top_margin_offset)
+    }
+
+    fn click(&mut self, window_pos : (u32,u32), unit : SelectUnit) {
+        if let Some(hex) = &mut self.hex {
+            hex.click(window_pos.0 as f32, window_pos.1 as f32 - self.top_margin.rect.px_size.1 as f32);
+            return self.update_cursors();
+        }
+        let pos = self.text_pos(window_pos);
+        self.editor.click(pos, unit);
+        self.update_cursors();
+    }
+    fn drag(&mut self, window_pos : (u32,u32)) {
+        if self.hex.is_some() {
+            return;
+        }
+        let pos = self.text_pos(window_pos);
+        self.editor.drag(pos);
+        self.update_cursors();
+    }
+
+    // While dragging above or below the text, scroll towards the mouse. Faster the further out it is.
+    //  Returns if it scrolled.
+    fn drag_scroll(&mut self, window_pos : (u32,u32), seconds : f64) -> bool {
+        let top = self.top_margin.rect.px_size.1 as f64;
+        let bottom = self.size.height.saturating_sub(STATUS_HEIGHT) as f64;
+        let y = window_pos.1 as f64;
+        let distance = if y < top { y - top } else if y >= bottom { y - bottom + 1.0 } else { return false };
+
+        let lines_per_second = 4.0 + distance.abs() / self.font_scale as f64 * 8.0;
+        self.editor.scroll_by(lines_per_second * seconds * distance.signum());
+        self.drag(window_pos);
+        true
+    }
+
+    fn animate(&mut self, seconds : f64) -> bool {
+        let moving = self.editor.animate(seconds);
+        if moving {
+            self.update_cursors();
+        }
+        moving
+    }
+
+    fn undo(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.undo();
+        self.update_cursors();
+    }
+    fn redo(&mut self) {
+        if self.hex.is_some() {
+            return;
+        }
+        self.editor.redo();
+        self.update_cursors();
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -408,57 +804,113 @@ impl State {
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment { // create one attachment for this render pass
                     view: &view,
                     resolve_target: None,
-                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear( wgpu::Color{r:bg_color.0 as f64,g:bg_color.1 as f64,b:bg_color.2 as f64, a :1.0} ), store: true },
+                    ops: wgpu::Operations { load: wgpu::LoadOp::Clear( wgpu::Color{r:BG_COLOR.0 as f64,g:BG_COLOR.1 as f64,b:BG_COLOR.2 as f64, a :1.0} ), store: true },
                 })],
                 depth_stencil_attachment: None,
             });
 
             render_pass.set_pipeline(&self.rect_pipeline.pipeline);
             // self.draw(&mut render_pass);
-            for cursor in &mut self.cursors {
-                cursor.rect.set_offset(&self.device, (0,top_margin_offset));
-                cursor.rect.draw(&mut render_pass);
+            if let Some(rect) = &self.ruler_rect {
+                rect.draw(&mut render_pass);
+            }
+            for rect in &self.selection_rects {
+                rect.draw(&mut render_pass);
+            }
+            for rect in &self.cursor_rects {
+                rect.draw(&mut render_pass);
             }
-            
+            // over the top of anything scrolled off the left of the text.
+            self.gutter.draw(&mut render_pass);
+            self.status_bar.draw(&mut render_pass);
+
             for rect in &self.rectangles {
                 rect.draw(&mut render_pass);
             }
 
-            self.top_margin.draw(&self.device,&mut render_pass,&mut self.glyph_brush,self.font_scale,self.modified);
+            self.top_margin.draw(&self.device,&mut render_pass,self.modified());
         }
 
         // ------------- Draw text ------------------
         // queue text draw
         let mut y_acc = 0; // y position in lines.
-        let offset = self.scroll as i64 * self.font_scale as i64;
-        for i in 0..self.lines.len() {
-            
-            let break_num = &self.lines[i].breaks.len();
-            for wrap in 0..break_num-1 {
-                let pos = (0.0, (y_acc * self.font_scale as i64 - offset + top_margin_offset) as f32);
-                
-                //eww
-                let f_text_color = rgb(text_color);
-                let text = Text::new(&self.lines[i].text[self.lines[i].breaks[wrap] .. self.lines[i].breaks[wrap+1]]).with_color([f_text_color.0,f_text_color.1,f_text_color.2,1.1]).with_scale(self.font_scale);
-                self.glyph_brush.queue(Section {
-                    screen_position: pos,
-                    bounds: (self.size.width as f32, self.size.height as f32),
-                    text: vec![text],
-                    layout: wgpu_glyph::Layout::default_single_line(),
-                    
-                    // ..Section::default() // line ending and v-h align
-                });
-
-                y_acc += 1;
+        let offset = self.editor.scroll_offset();
+        let offset_x = self.editor.scroll_x as f32 - self.editor.text_x();
+        let line_height = self.font_scale as i64;
+        let f_text_color = rgb(TEXT_COLOR);
+        if let Some(hex) = &self.hex {
+            for row in hex.visible_rows() {
+                let y = (hex.row_y(row) + top_margin_offset) as f32;
+                for (x,text) in hex.row_text(row) {
+                    let text = Text::new(&text).with_color([f_text_color.0,f_text_color.1,f_text_color.2,1.0]).with_scale(self.font_scale);
+                    self.glyph_brush.queue(Section {
+                        screen_position : (x, y),
+                        bounds : (self.size.width as f32, self.size.height as f32),
+                        text : vec![text],
+                        layout : wgpu_glyph::Layout::default_single_line(),
+                    });
+                }
             }
+        } else {
+            let document = &self.editor.document;
+            for (i,line) in document.lines.iter().enumerate() {
+                let rows = line.glyphs.len() as i64;
+                // only ask the buffer for lines that are on screen.
+                if (y_acc + rows) * line_height - offset < 0 {
+                    y_acc += rows;
+                    continue;
+                }
+                if y_acc * line_height - offset > self.size.height as i64 {
+                    break;
+                }
 
+                let text = document.line(i);
+                for wrap in 0..line.breaks.len()-1 {
+                    let y = (y_acc * line_height - offset + top_margin_offset) as f32;
+
+                    // glyph_brush doesn't know about tab stops, so each run between tabs goes where the layout put it.
+                    let mut start = line.breaks[wrap];
+                    for run in text[line.breaks[wrap] .. line.breaks[wrap+1]].split('\t') {
+                        if !run.is_empty() {
+                            let text = Text::new(run).with_color([f_text_color.0,f_text_color.1,f_text_color.2,1.1]).with_scale(self.font_scale);
+                            self.glyph_brush.queue(Section {
+                                screen_position: (line.x_at(wrap, start) - offset_x, y),
+                                bounds: (self.size.width as f32 + offset_x, self.size.height as f32), // scrolled sideways, the run starts left of the window
+                                text: vec![text],
+                                layout: wgpu_glyph::Layout::default_single_line(),
+
+                                // ..Section::default() // line ending and v-h align
+                            });
+                        }
+                        start += run.len() + 1;
+                    }
+
+                    y_acc += 1;
+                }
+
+            }
         }
 
-        
+        // draw text, cut off where it meets the gutter and the top margin.
+        let text_x = self.hex.as_ref().map_or(self.editor.text_x(), |hex| hex.text_x()) as u32;
+        let region = wgpu_glyph::Region {
+            x : text_x.min(self.size.width),
+            y : (top_margin_offset as u32).min(self.size.height),
+            width : self.size.width.saturating_sub(text_x),
+            height : self.size.height.saturating_sub(top_margin_offset as u32 + STATUS_HEIGHT)
+        };
+        let transform = wgpu_glyph::orthographic_projection(self.size.width, self.size.height);
+        match self.glyph_brush.draw_queued_with_transform_and_scissoring(&self.device, &mut self.staging_belt, &mut encoder, &view, transform, region) {
+            Ok(_) => {},
+            Err(e) => println!("error! : {}", e),
+        }
 
-       
-        
-        // draw text
+        // then the line numbers and the file name, on top.
+        let space = self.editor.layout.column_x(1);
+        let labels = self.hex.as_ref().map_or_else(|| self.editor.gutter_labels(), |hex| hex.gutter_labels());
+        self.gutter.queue_text(&mut self.glyph_brush, &labels, space, top_margin_offset, self.font_scale);
+        self.top_margin.queue_text(&mut self.glyph_brush, self.font_scale);
+        self.status_bar.queue_text(&mut self.glyph_brush, self.font_scale);
         match self.glyph_brush.draw_queued(&self.device, &mut self.staging_belt, &mut encoder, &view, self.size.width, self.size.height) {
             Ok(_) => {},
             Err(e) => println!("error! : {}", e),
@@ -488,10 +940,12 @@ pub async fn run() {
 
     let mut state = State::new(&window,file_name).await;
 
-    // unused
     let mut mod_state : ModifiersState = ModifiersState::default();
 
-    let mut cursor_pos = (0,0);    
+    let mut cursor_pos = (0,0);
+    // for telling double and triple clicks apart. (when, where, how many in a row)
+    let mut last_click : (Instant,(u32,u32),u32) = (Instant::now(),(0,0),0);
+    let mut last_tick = Instant::now();
 
     event_loop.run(move |event, _, control_flow| match event {
         Event::RedrawRequested(window_id) if window_id == window.id() => {
@@ -507,15 +961,24 @@ pub async fn run() {
             // Event::RedrawRequested will only run once, unless we request it.
             // This is super slow! Maybe should give control to system now using a target fps thing.
             // window.request_redraw();
+            let seconds = last_tick.elapsed().as_secs_f64();
+            if state.editor.dragging() && state.drag_scroll(cursor_pos, seconds) {
+                window.request_redraw();
+            }
+            // keep drawing until smooth scrolling gets where it's going.
+            if state.animate(seconds) {
+                window.request_redraw();
+            }
+            last_tick = Instant::now();
         }
 
         Event::WindowEvent {
             ref event,
             window_id,
-        } if window_id == window.id() => if !state.input(event) {
+        } if window_id == window.id() && !state.input(event) => {
             match event {
-                WindowEvent::CloseRequested
-                | WindowEvent::KeyboardInput {
+                WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+                WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
                             state: ElementState::Pressed,
@@ -523,7 +986,18 @@ pub async fn run() {
                             ..
                         },
                     ..
-                } => *control_flow = ControlFlow::Exit,
+                } => {
+                    // get rid of an error, then extra cursors and selections first.
+                    if state.top_margin.error.is_some() {
+                        state.top_margin.error = None;
+                        window.request_redraw();
+                    } else if state.hex.is_none() && state.editor.can_collapse() {
+                        state.collapse_cursors();
+                        window.request_redraw();
+                    } else {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                }
                 WindowEvent::Resized(size) => {
                     state.resize(*size);
                 },
@@ -531,28 +1005,149 @@ pub async fn run() {
                     state.resize(**new_inner_size);
                 },
 
-                WindowEvent::KeyboardInput {input: KeyboardInput{state : ElementState::Pressed, virtual_keycode ,..},..} => {
-                    if let Some(code) = *virtual_keycode {
-                        use VirtualKeyCode::*;
-                        match code {
-                            Left => {
-                                state.move_cursor(CursorMovement::Left);
-                                window.request_redraw();
-                            }
-                            Right => {
-                                state.move_cursor(CursorMovement::Right);
-                                window.request_redraw();
-                            }
-                            Up => {
-                                state.move_cursor(CursorMovement::Up);
-                                window.request_redraw();
-                            }
-                            Down => {
-                                state.move_cursor(CursorMovement::Down);
-                                window.request_redraw();
-                            }
-                            _ => {}
+                WindowEvent::KeyboardInput {input: KeyboardInput{state : ElementState::Pressed, virtual_keycode : Some(code) ,..},..} => {
+                    use VirtualKeyCode::*;
+                    match code {
+                        Left if mod_state.ctrl() => {
+                            state.move_cursor(CursorMovement::WordLeft, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        Right if mod_state.ctrl() => {
+                            state.move_cursor(CursorMovement::WordRight, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        Left => {
+                            state.move_cursor(CursorMovement::Left, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        Right => {
+                            state.move_cursor(CursorMovement::Right, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        Up if mod_state.ctrl() && mod_state.alt() => {
+                            state.add_cursor_vertical(true);
+                            window.request_redraw();
+                        }
+                        Down if mod_state.ctrl() && mod_state.alt() => {
+                            state.add_cursor_vertical(false);
+                            window.request_redraw();
+                        }
+                        Up => {
+                            state.move_cursor(CursorMovement::Up, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        Down => {
+                            state.move_cursor(CursorMovement::Down, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        Home if mod_state.ctrl() => {
+                            state.move_cursor(CursorMovement::DocumentStart, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        End if mod_state.ctrl() => {
+                            state.move_cursor(CursorMovement::DocumentEnd, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        Home => {
+                            state.move_cursor(CursorMovement::Home, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        End => {
+                            state.move_cursor(CursorMovement::End, mod_state.shift());
+                            window.request_redraw();
+                        }
+                        PageUp => {
+                            state.move_cursor(CursorMovement::PageUp(state.editor.page_rows()), mod_state.shift());
+                            window.request_redraw();
+                        }
+                        PageDown => {
+                            state.move_cursor(CursorMovement::PageDown(state.editor.page_rows()), mod_state.shift());
+                            window.request_redraw();
+                        }
+                        Z if mod_state.alt() => {
+                            state.toggle_wrap();
+                            window.request_redraw();
+                        }
+                        L if mod_state.alt() => {
+                            state.cycle_line_numbers();
+                            window.request_redraw();
+                        }
+                        I if mod_state.alt() => {
+                            state.toggle_wrap_indent();
+                            window.request_redraw();
+                        }
+                        M if mod_state.alt() => {
+                            state.toggle_logical_lines();
+                            window.request_redraw();
+                        }
+                        E if mod_state.alt() => {
+                            state.cycle_line_ending();
+                            window.request_redraw();
+                        }
+                        N if mod_state.alt() => {
+                            state.toggle_final_newline();
+                            window.request_redraw();
+                        }
+                        T if mod_state.alt() => {
+                            state.cycle_indent_width();
+                            window.request_redraw();
+                        }
+                        H if mod_state.alt() => {
+                            state.toggle_hex();
+                            window.request_redraw();
+                        }
+                        Insert => {
+                            state.toggle_insert();
+                            window.request_redraw();
+                        }
+                        Z if mod_state.ctrl() && mod_state.shift() => {
+                            state.redo();
+                            window.request_redraw();
+                        }
+                        Z if mod_state.ctrl() => {
+                            state.undo();
+                            window.request_redraw();
+                        }
+                        Y if mod_state.ctrl() => {
+                            state.redo();
+                            window.request_redraw();
+                        }
+                        L if mod_state.ctrl() => {
+                            state.center_cursor();
+                            window.request_redraw();
+                        }
+                        D if mod_state.ctrl() => {
+                            state.add_next_occurrence();
+                            window.request_redraw();
                         }
+                        Delete if mod_state.ctrl() => {
+                            state.delete(Deletion::WordRight);
+                            window.request_redraw();
+                        }
+                        Delete => {
+                            state.delete(Deletion::Forward);
+                            window.request_redraw();
+                        }
+                        Back if mod_state.ctrl() => {
+                            state.delete(Deletion::WordLeft);
+                            window.request_redraw();
+                        }
+                        K if mod_state.ctrl() && mod_state.shift() => {
+                            state.delete(Deletion::Line);
+                            window.request_redraw();
+                        }
+                        C if mod_state.ctrl() => {
+                            state.copy();
+                        }
+                        X if mod_state.ctrl() => {
+                            state.cut();
+                            window.request_redraw();
+                        }
+                        V if mod_state.ctrl() => {
+                            state.paste(ClipboardKind::Clipboard);
+                            window.request_redraw();
+                        }
+                        _ => {}
                     }
                 }
 
@@ -562,6 +1157,12 @@ pub async fn run() {
                         // if mod_state.ctrl() && *character == 's' {
                         // Save time!
                         state.save_file();
+                    }else if *character == '\u{8}' && mod_state.ctrl() {
+                        // ctrl+backspace is a word, handled with the keys.
+                    }else if mod_state.alt() && !mod_state.ctrl() {
+                        // alt+letter is a command, handled with the keys. (ctrl+alt is altgr on windows, which types)
+                    }else if *character == '\t' && mod_state.shift() {
+                        state.outdent();
                     }else{
                         state.insert_cursor(*character);
                     }
@@ -574,46 +1175,86 @@ pub async fn run() {
                 WindowEvent::ModifiersChanged(mods) => {
                     mod_state = *mods;
                 }
-                
 
                 // Mouse stuff -------------
-                WindowEvent::MouseInput { device_id, state: el_state, button, modifiers } => {
-                    if let MouseButton::Left = *button {
-                        if let ElementState::Released = *el_state {
-
-                            // Go through all buttons
-                            if state.top_margin.exit_button.does_click(cursor_pos) {
-                                control_flow.set_exit();
-                            }
-
-                        }
-                    }
+                // Go through all buttons
+                WindowEvent::MouseInput { state: ElementState::Released, button: MouseButton::Left, .. }
+                    if state.top_margin.exit_button.does_click(cursor_pos) => {
+                    control_flow.set_exit();
+                }
+                WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. } if mod_state.alt() => {
+                    let pos = state.text_pos(cursor_pos);
+                    state.add_cursor(pos);
+                    window.request_redraw();
                 }
-                WindowEvent::CursorMoved { device_id, position, modifiers } => {
-                    cursor_pos = (position.x as u32,position.y as u32);
+                WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. }
+                    if cursor_pos.1 >= state.size.height.saturating_sub(STATUS_HEIGHT) => {
+                    state.click_status(cursor_pos.0);
+                    window.request_redraw();
                 }
-                WindowEvent::MouseWheel { device_id, delta, phase, .. }  => {
-                    // scroll!
-                    match delta {
-                        MouseScrollDelta::LineDelta(x, y) => {
-                            // mouse scroll wheel scrolling
-                            state.scroll -= *y as f64;
-                            for cursor in &mut state.cursors {
-                                // TODO: Remove offset from the Cursor struct.
-                                // cursor.rect.set_offset(&state.device, (0,(state.scroll as f32 * state.font_scale) as i64));
-                                cursor.update_cursor(&state.device,&state.glyph_brush, state.scroll as i64 * state.font_scale as i64, &state.lines);
-                            }
-                            println!("Scrolling lines ({},{})",x,y);
-                        },
-                        MouseScrollDelta::PixelDelta( PhysicalPosition{x,y}) => {
-                            // mouse pad scrolling
-                            state.scroll -= *y as f64;
-                            for cursor in &mut state.cursors{
-                                cursor.update_cursor(&state.device,&state.glyph_brush, state.scroll as i64 * state.font_scale as i64, &state.lines);
-                            }
-                            println!("Scrolling pixels ({},{})",x,y);
-                        },
+                WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. }
+                    if cursor_pos.1 >= state.top_margin.rect.px_size.1 && (cursor_pos.0 as f32) < state.editor.text_x() => {
+                    // a line number selects its whole line.
+                    last_click = (Instant::now(),cursor_pos,3);
+                    state.click(cursor_pos, SelectUnit::Line);
+                    window.request_redraw();
+                }
+                WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Left, .. }
+                    if cursor_pos.1 >= state.top_margin.rect.px_size.1 => {
+                    // clicks close together in time and space count up. 1, 2, 3, then back to 1.
+                    let (when,pos,count) = last_click;
+                    let near = pos.0.abs_diff(cursor_pos.0) <= 4 && pos.1.abs_diff(cursor_pos.1) <= 4;
+                    let count = if near && when.elapsed() < Duration::from_millis(400) { count % 3 + 1 } else { 1 };
+                    last_click = (Instant::now(),cursor_pos,count);
+
+                    let unit = match count { 1 => SelectUnit::Character, 2 => SelectUnit::Word, _ => SelectUnit::Line };
+                    state.click(cursor_pos, unit);
+                    window.request_redraw();
+                }
+                WindowEvent::MouseInput { state: ElementState::Released, button: MouseButton::Left, .. } => {
+                    state.end_drag();
+                    window.request_redraw();
+                }
+                WindowEvent::MouseInput { state: ElementState::Pressed, button: MouseButton::Middle, .. } => {
+                    // paste where the mouse is, like everywhere else on X11.
+                    state.click(cursor_pos, SelectUnit::Character);
+                    state.end_drag();
+                    state.paste(ClipboardKind::Primary);
+                    window.request_redraw();
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    cursor_pos = (position.x.max(0.0) as u32,position.y.max(0.0) as u32);
+                    if state.editor.dragging() {
+                        state.drag(cursor_pos);
+                        window.request_redraw();
                     }
+                }
+                WindowEvent::MouseWheel { delta, .. }  => {
+                    // scroll! bytes don't go sideways, or smoothly.
+                    if let Some(hex) = &mut state.hex {
+                        match delta {
+                            MouseScrollDelta::LineDelta(_, y) => hex.scroll_by(-*y as f64 * state.editor.wheel_lines),
+                            MouseScrollDelta::PixelDelta( PhysicalPosition{y,..}) => hex.scroll_by_pixels(-*y)
+                        }
+                    } else {
+                        match delta {
+                            // shift turns the wheel sideways.
+                            MouseScrollDelta::LineDelta(_, y) if mod_state.shift() => {
+                                state.editor.scroll_x_by(-*y as f64 * state.font_scale as f64 * 3.0);
+                            },
+                            MouseScrollDelta::LineDelta(x, y) => {
+                                // mouse scroll wheel scrolling
+                                state.editor.wheel(-*y as f64);
+                                state.editor.scroll_x_by(-*x as f64 * state.font_scale as f64 * 3.0);
+                            },
+                            MouseScrollDelta::PixelDelta( PhysicalPosition{x,y}) => {
+                                // mouse pad scrolling, already in pixels.
+                                state.editor.scroll_by_pixels(-*y);
+                                state.editor.scroll_x_by(-*x);
+                            },
+                        }
+                    }
+                    state.update_cursors();
                     window.request_redraw();
                 }
                 // -------------------------
@@ -624,9 +1265,9 @@ pub async fn run() {
     });
 }
 
- 
 
- 
+
+
 
 fn main() {
     pollster::block_on(run());
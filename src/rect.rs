@@ -30,7 +30,7 @@ impl RectPipeline {
                 entry_point: "fs_main",
                 targets : &[
                     Some(wgpu::ColorTargetState{
-                        format:format,
+                        format,
                         blend: Some(wgpu::BlendState::REPLACE),
                         write_mask: wgpu::ColorWrites::ALL
                     })
@@ -93,11 +93,12 @@ impl Vertex {
 fn world_space(screen_size : (u32,u32),x:i64,y:i64,width : u32,height:u32) -> (f32,f32,f32,f32) {
     (
         x as f32 /(screen_size.0 as f32 / 2.0) - 1.0,
-        -1.0 * (y as f32/(screen_size.1 as f32 / 2.0) - 1.0),
+        -(y as f32/(screen_size.1 as f32 / 2.0) - 1.0),
         width as f32/screen_size.0 as f32 * 2.0,
         height as f32/screen_size.1 as f32 * 2.0
     )
 }
+#[allow(dead_code)]
 fn screen_space(screen_size: (u32,u32),x:f32,y:f32,width:f32,height:f32) -> (i64,i64,u32,u32) {
     (
         ( (x+1.0) * (screen_size.0 as f32 / 2.0) ) as i64,
@@ -108,6 +109,7 @@ fn screen_space(screen_size: (u32,u32),x:f32,y:f32,width:f32,height:f32) -> (i64
 }
 
 pub struct Rect {
+    #[allow(dead_code)]
     vertices : [Vertex;6],
     size :(f32,f32),
     pos : (f32,f32),
@@ -153,6 +155,7 @@ impl Rect {
         render_pass.draw(0..6,0..1);
     }
 
+    #[allow(dead_code)]
     pub fn get_pos(&self)->(i64,i64) {
         let (x,y,_,_) = screen_space(self.screen_size, self.pos.0, self.pos.1, self.size.0, self.size.1);
         (x,y)
@@ -0,0 +1,69 @@
+use manip::clipboard::{Clipboard, ClipboardKind, MemoryClipboard};
+
+// The desktop's clipboard. Anything it can't do (no display, no PRIMARY on this
+//  platform) falls back to a clipboard only this window can see.
+pub struct SystemClipboard {
+    clipboard : Option<arboard::Clipboard>,
+    fallback : MemoryClipboard,
+    error : Option<String> // for the editor to show, instead of the terminal nobody is looking at
+}
+
+impl SystemClipboard {
+    pub fn new() -> Self {
+        let (clipboard,error) = match arboard::Clipboard::new() {
+            Ok(clipboard) => (Some(clipboard), None),
+            Err(e) => (None, Some(format!("No system clipboard, copying inside the editor only : {}", e)))
+        };
+        SystemClipboard { clipboard, fallback : MemoryClipboard::default(), error }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn linux_kind(kind : ClipboardKind) -> arboard::LinuxClipboardKind {
+    match kind {
+        ClipboardKind::Clipboard => arboard::LinuxClipboardKind::Clipboard,
+        ClipboardKind::Primary => arboard::LinuxClipboardKind::Primary
+    }
+}
+
+impl Clipboard for SystemClipboard {
+    fn get(&mut self, kind : ClipboardKind) -> Option<String> {
+        let Some(clipboard) = &mut self.clipboard else { return self.fallback.get(kind) };
+
+        #[cfg(target_os = "linux")]
+        let text = {
+            use arboard::GetExtLinux;
+            clipboard.get().clipboard(linux_kind(kind)).text()
+        };
+        #[cfg(not(target_os = "linux"))]
+        let text = match kind {
+            ClipboardKind::Clipboard => clipboard.get_text(),
+            ClipboardKind::Primary => return self.fallback.get(kind)
+        };
+
+        text.ok()
+    }
+
+    fn set(&mut self, kind : ClipboardKind, text : String) {
+        let Some(clipboard) = &mut self.clipboard else { return self.fallback.set(kind, text) };
+
+        #[cfg(target_os = "linux")]
+        let result = {
+            use arboard::SetExtLinux;
+            clipboard.set().clipboard(linux_kind(kind)).text(text)
+        };
+        #[cfg(not(target_os = "linux"))]
+        let result = match kind {
+            ClipboardKind::Clipboard => clipboard.set_text(text),
+            ClipboardKind::Primary => return self.fallback.set(kind, text)
+        };
+
+        if let Err(e) = result {
+            self.error = Some(format!("Failed to copy : {}", e));
+        }
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}